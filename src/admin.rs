@@ -0,0 +1,390 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use config::Config;
+use log::LevelFilter;
+
+use super::error::{exit, YiError, YiErrorKind, YiResult, YiResultExt};
+use super::health::Health;
+use super::rpc::Remote;
+use super::version::Version;
+use super::{crash, logger, shutdown};
+
+// how long `ctl` waits for a reply
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+pub type Handler = Box<dyn Fn(&[String]) -> YiResult<String> + Send + Sync>;
+pub type Hook = Box<dyn Fn(&Config) -> YiResult<()> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    Bind,
+    Connect,
+    Command,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Bind    => write!(f, "admin socket"),
+            Error::Connect => write!(f, "connecting to the admin socket"),
+            Error::Command => write!(f, "unknown admin command"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Bind    => "YI-ADM-001",
+            Error::Connect => "YI-ADM-002",
+            Error::Command => "YI-ADM-003",
+        }
+    }
+}
+
+impl From<Error> for YiErrorKind {
+    fn from(e: Error) -> YiErrorKind {
+        YiErrorKind::Code(e.code(), e.to_string())
+    }
+}
+
+impl From<Error> for YiError {
+    fn from(e: Error) -> YiError {
+        YiError::from(YiErrorKind::from(e))
+    }
+}
+
+const BUILTIN: &[(&str, &str)] = &[
+    ("help", "lists the commands"),
+    ("status", "name, pid, uptime, log level and health"),
+    ("health", "the health report as JSON"),
+    ("version", "version, `version -v` with build details"),
+    ("reload", "reloads the config files"),
+    ("log-level", "sets the log level, e.g. `log-level debug`"),
+    ("config", "prints the config, secrets masked"),
+    ("shutdown", "stops the process gracefully"),
+];
+
+struct Command {
+    help: String,
+    handler: Handler,
+}
+
+// Control socket of a running process, one command line per connection
+// answered with one JSON line, see `call` for the client side. Built by
+// `App::admin`, apps add their own commands before `spawn`:
+//
+//     app.admin().command("flush", "flushes the cache", |_| Ok(cache.flush()))
+//         .spawn()?;
+pub struct Admin {
+    path: PathBuf,
+    name: String,
+    version: Version,
+    start: Instant,
+    config: Arc<RwLock<Config>>,
+    health: Arc<Health>,
+    commands: BTreeMap<String, Command>,
+    on_reload: Vec<Hook>,
+    on_shutdown: Vec<Box<dyn Fn() + Send + Sync>>,
+    // the socket file this process created, removed on drop
+    bound: Option<PathBuf>,
+}
+
+impl Admin {
+    pub fn new<P: AsRef<Path>>(path: P, name: &str, version: Version, config: Config) -> Self {
+        Admin {
+            path: path.as_ref().to_path_buf(),
+            name: name.to_string(),
+            version,
+            start: Instant::now(),
+            config: Arc::new(RwLock::new(config)),
+            health: Arc::new(Health::new()),
+            commands: BTreeMap::new(),
+            on_reload: Vec::new(),
+            on_shutdown: Vec::new(),
+            bound: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // the config as of the last `reload`
+    pub fn config(&self) -> Arc<RwLock<Config>> {
+        self.config.clone()
+    }
+
+    // the registry `health` and `status` report, the app's from `App::admin`
+    pub fn health(mut self, health: Arc<Health>) -> Self {
+        self.health = health;
+        self
+    }
+
+    pub fn command<F>(mut self, name: &str, help: &str, handler: F) -> Self
+    where F: Fn(&[String]) -> YiResult<String> + Send + Sync + 'static
+    {
+        self.commands.insert(name.to_string(), Command {
+            help: help.to_string(),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    // called with the new config after `reload`, in registration order
+    pub fn on_reload<F>(mut self, hook: F) -> Self
+    where F: Fn(&Config) -> YiResult<()> + Send + Sync + 'static
+    {
+        self.on_reload.push(Box::new(hook));
+        self
+    }
+
+    // called by `shutdown`, which triggers `shutdown::token` when there is none
+    pub fn on_shutdown<F: Fn() + Send + Sync + 'static>(mut self, hook: F) -> Self {
+        self.on_shutdown.push(Box::new(hook));
+        self
+    }
+
+    pub fn spawn(mut self) -> YiResult<thread::JoinHandle<()>> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).to_yikind(Error::Bind).with_field("path", dir)?;
+        }
+        // a socket file left by a dead process
+        if UnixStream::connect(&self.path).is_err() {
+            remove_socket(&self.path);
+        }
+        let listener = UnixListener::bind(&self.path).to_yikind(Error::Bind)
+            .with_field("path", &self.path)
+            .hint("is another instance running?")?;
+        self.bound = Some(self.path.clone());
+        // `config` shows secrets and `shutdown` stops the app, owner only
+        fs::set_permissions(&self.path, fs::Permissions::from_mode(0o600)).to_yikind(Error::Bind)
+            .with_field("path", &self.path)?;
+        log::info!("admin socket on {}", self.path.display());
+
+        let admin = Arc::new(self);
+        let reload = admin.clone();
+        shutdown::on_reload(move || reload.reload());
+        let path = admin.path.clone();
+        shutdown::hook("admin socket", Duration::from_secs(1), move || remove_socket(&path));
+
+        Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(s)  => {
+                        let admin = admin.clone();
+                        thread::spawn(move || admin.connection(s));
+                    }
+                    Err(e) => log::warn!("admin socket: {}", e),
+                }
+            }
+        }))
+    }
+
+    fn connection(&self, stream: UnixStream) {
+        let mut line = String::new();
+        let _ = stream.set_read_timeout(Some(TIMEOUT));
+        if let Err(e) = BufReader::new(&stream).read_line(&mut line) {
+            log::warn!("admin socket: {}", e);
+            return;
+        }
+
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        log::info!("admin command: {}", line.trim());
+        let reply = self.dispatch(&args).map_err(|e| Remote::from(&e));
+        let shutdown = reply.is_ok() && args.first().map(String::as_str) == Some("shutdown");
+
+        let mut stream = &stream;
+        let text = serde_json::to_string(&reply).unwrap_or_default();
+        let _ = writeln!(stream, "{}", text).and_then(|_| stream.flush());
+
+        if shutdown {
+            self.shutdown();
+        }
+    }
+
+    fn dispatch(&self, args: &[String]) -> YiResult<String> {
+        let (cmd, args) = match args.split_first() {
+            Some((cmd, args)) => (cmd.as_str(), args),
+            None              => ("help", args),
+        };
+
+        match cmd {
+            "help"      => Ok(self.help()),
+            "status"    => Ok(self.status()),
+            "health"    => serde_json::to_string(&self.health.snapshot()).to_yierr("health")
+                .map(|s| s + "\n"),
+            "version"   => Ok(match args.first().map(String::as_str) {
+                Some("-v") | Some("--verbose") => self.version.to_full(),
+                _ => format!("{}\n", self.version),
+            }),
+            "reload"    => self.reload().map(|_| "config reloaded\n".to_string()),
+            "log-level" => {
+                let level = args.first().map(String::as_str).unwrap_or_default();
+                let level = LevelFilter::from_str(level)
+                    .map_err(|_| YiError::from(YiErrorKind::Opt(format!("unknown log level: {}", level)))
+                             .hint("off, error, warn, info, debug or trace"))?;
+                logger::logger().map(|l| l.set_level(level))
+                    .unwrap_or_else(|| log::set_max_level(level));
+                Ok(format!("log level {}\n", level))
+            }
+            "config"    => {
+                let config = self.config.read().unwrap_or_else(|e| e.into_inner()).clone();
+                let value = crash::sanitize(config.try_into().unwrap_or_default());
+                serde_json::to_string_pretty(&value).to_yierr("config").map(|s| s + "\n")
+            }
+            "shutdown"  => Ok("shutting down\n".to_string()),
+            cmd         => match self.commands.get(cmd) {
+                Some(c) => (c.handler)(args),
+                None    => Err(YiError::from(Error::Command).with_field("command", cmd)
+                               .hint("`ctl help` lists the commands")),
+            },
+        }
+    }
+
+    fn help(&self) -> String {
+        BUILTIN.iter().map(|(c, h)| (c.to_string(), h.to_string()))
+            .chain(self.commands.iter().map(|(c, cmd)| (c.clone(), cmd.help.clone())))
+            .map(|(c, h)| format!("{:<12} {}\n", c, h))
+            .collect()
+    }
+
+    fn status(&self) -> String {
+        let health = self.health.snapshot();
+        format!("name: {}\npid: {}\nuptime: {}s\nversion: {}\nlog-level: {}\nhealth: {}\nready: {}\n",
+                self.name, process::id(), self.start.elapsed().as_secs(),
+                self.version.short(), log::max_level(), health.status, health.ready)
+    }
+
+    // re-read the config sources, values set from the command line stay
+    fn reload(&self) -> YiResult<()> {
+        let mut config = self.config.read().unwrap_or_else(|e| e.into_inner()).clone();
+        config.refresh().to_yierr("reload")?;
+
+        for hook in &self.on_reload {
+            hook(&config)?;
+        }
+        crash::set_config(&config);
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = config;
+        log::info!("config reloaded");
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        log::info!("shutdown requested on the admin socket");
+        if self.on_shutdown.is_empty() {
+            shutdown::stop("admin", exit::OK);
+        }
+        for hook in &self.on_shutdown {
+            hook();
+        }
+    }
+}
+
+impl Drop for Admin {
+    fn drop(&mut self) {
+        if let Some(path) = &self.bound {
+            remove_socket(path);
+        }
+    }
+}
+
+// never a file of another kind that happens to be at the path
+fn remove_socket(path: &Path) {
+    if fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+// Send one command to the admin socket at `path`, the output of the
+// command or its error as a YiError.
+pub fn call<P: AsRef<Path>>(path: P, args: &[String]) -> YiResult<String> {
+    let path = path.as_ref();
+    let mut stream = UnixStream::connect(path).to_yikind(Error::Connect)
+        .with_field("path", path)
+        .hint("is the process running?")?;
+    stream.set_read_timeout(Some(TIMEOUT)).to_yikind(Error::Connect)?;
+    writeln!(stream, "{}", args.join(" ")).to_yikind(Error::Connect)?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).to_yikind(Error::Connect)?;
+    let reply: Result<String, Remote> = serde_json::from_str(&line).to_yikind(Error::Connect)
+        .with_field("reply", line.trim())?;
+    reply.map_err(YiError::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use crate::health;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn commands() {
+        let dir = env::temp_dir().join(format!("yiapp-admin-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("app.toml");
+        fs::write(&file, "workers = 2\n").unwrap();
+
+        let mut c = Config::default();
+        c.merge(config::File::from(file.as_path())).unwrap();
+        c.set("spawn", true).unwrap();
+
+        let path = dir.join("app.sock");
+        let health = Arc::new(Health::new());
+        health.degraded("cache", "cold");
+        let admin = Admin::new(&path, "app", Version::default(), c)
+            .health(health.clone())
+            .command("echo", "echoes", |args| Ok(args.join(" ")))
+            .on_reload(|c| c.get_int("workers").map(|_| ()).to_yierr("workers"));
+        let config = admin.config();
+        admin.spawn().unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        assert_eq!(call(&path, &args("echo a b")).unwrap(), "a b");
+        // a second instance neither takes over nor removes the live socket
+        drop(Admin::new(&path, "app", Version::default(), Config::default()));
+        let e = Admin::new(&path, "app", Version::default(), Config::default()).spawn().unwrap_err();
+        assert_eq!(e.code(), "YI-ADM-001");
+        assert_eq!(call(&path, &args("echo c")).unwrap(), "c");
+        assert!(call(&path, &args("help")).unwrap().contains("echo         echoes"));
+        let status = call(&path, &args("status")).unwrap();
+        assert!(status.contains("name: app") && status.contains("health: degraded"));
+        let report: health::Report = serde_json::from_str(&call(&path, &args("health")).unwrap()).unwrap();
+        assert_eq!(report.checks["cache"].message.as_deref(), Some("cold"));
+        assert!(call(&path, &args("config")).unwrap().contains("\"workers\": 2"));
+
+        fs::write(&file, "workers = 8\n").unwrap();
+        call(&path, &args("reload")).unwrap();
+        assert_eq!(config.read().unwrap().get_int("workers").unwrap(), 8);
+        assert!(config.read().unwrap().get_bool("spawn").unwrap());
+
+        fs::write(&file, "workers = \"many\"\n").unwrap();
+        assert!(call(&path, &args("reload")).is_err());
+        assert_eq!(config.read().unwrap().get_int("workers").unwrap(), 8);
+
+        let e = call(&path, &args("nope")).unwrap_err();
+        assert_eq!(e.code(), "YI-ADM-003");
+        assert_eq!(e.fields()["command"], "nope");
+        assert_eq!(call(&path, &args("log-level loud")).unwrap_err().code(), "YI-CLI-004");
+
+        fs::remove_dir_all(dir).unwrap();
+        assert_eq!(call(&path, &args("status")).unwrap_err().code(), "YI-ADM-002");
+    }
+}
@@ -5,30 +5,82 @@ use std::hash::Hash;
 use std::env;
 use std::path::PathBuf;
 use std::collections::HashMap;
-use serde::Deserialize;
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
 use config::Config;
 
-use failure::{Fail};
-use super::error::{YiResult, YiResultExt};
+use super::error::{exit, YiError, YiErrorKind, YiResult, YiResultExt};
+use super::code;
+use super::logger::{self, LogConfig, Logger};
+use super::color::{self, scan_value, ColorChoice};
+use super::crash;
+use super::version::Version;
+use super::migrate::Migrations;
+use super::i18n::{self, Locale};
+use super::rpc::{self, RpcConfig};
+use super::shutdown::{self, Shutdown};
+use super::component::{Component, Components};
+use super::health::Health;
+#[cfg(unix)]
+use super::admin::{self, Admin};
+#[cfg(unix)]
+use super::health::Report;
+#[cfg(unix)]
+use super::daemon::{self, Handshake, Hardening, Startup};
+#[cfg(all(unix, feature = "systemd"))]
+use super::systemd::{self, Unit};
 
 const CLONE_SPAWN: &str = "__CLONE_SPAWN__";
 
-#[derive(Debug, Fail)]
+type StartupHook = (String, Box<dyn FnOnce() -> YiResult<()> + Send>);
+
+#[derive(Debug)]
 pub enum Error {
-    #[fail(display = "loading from file")]
     File,
-
-    #[fail(display = "loading from environment")]
     Env,
-
-    #[fail(display = "failed to match command argument")]
     CmdArg,
-
-    #[fail(display = "file to log")]
     LogFile,
-
-    #[fail(display = "file to error")]
     ErrFile,
+    LogConf,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::File    => write!(f, "loading from file"),
+            Error::Env     => write!(f, "loading from environment"),
+            Error::CmdArg  => write!(f, "failed to match command argument"),
+            Error::LogFile => write!(f, "file to log"),
+            Error::ErrFile => write!(f, "file to error"),
+            Error::LogConf => write!(f, "log configuration"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::File    => "YI-CFG-001",
+            Error::Env     => "YI-CFG-002",
+            Error::CmdArg  => "YI-CFG-003",
+            Error::LogFile => "YI-IO-002",
+            Error::ErrFile => "YI-IO-003",
+            Error::LogConf => "YI-CFG-004",
+        }
+    }
+}
+
+impl From<Error> for YiErrorKind {
+    fn from(e: Error) -> YiErrorKind {
+        YiErrorKind::Code(e.code(), e.to_string())
+    }
 }
 
 #[derive(Debug, PartialEq, Hash)]
@@ -63,32 +115,268 @@ pub type Configs<T> = HashMap<T, Config>;
 
 pub struct App<'a, T> {
     name: T,
+    about: String,
     args: Config,
     clap: clap::App<'a, 'a>,
+    // `Desc::Version` set clap's version
+    clap_version: bool,
+    // subcommands of the app, they replace built-ins of the same name
+    subcommands: Vec<String>,
     config: Configs<T>,
-    cdir: PathBuf
+    cdir: PathBuf,
+    version: Version,
+    migrations: HashMap<T, Migrations>,
+    startup: Mutex<Vec<StartupHook>>,
+    components: Arc<Mutex<Components>>,
+    health: Arc<Health>,
 }
 
 impl<'a, T> App<'a, T>
 where T: Deref<Target=str> + AsRef<str> + Display + Hash + Eq + Clone,
 {
-    pub fn new(desc: Opt<'a, T>, opts: Opts<'a, T>) -> Self {
+    // `version` of the app itself, `yiapp_version!()` in its crate
+    pub fn new(version: Version, desc: Opt<'a, T>, opts: Opts<'a, T>) -> Self {
         let name = desc.0.clone();
+        let about = desc.1.iter().find_map(|d| match d {
+            Desc::About(v) => Some(v.to_string()),
+            _              => None,
+        }).unwrap_or_else(|| name.to_string());
         let args = Config::default();
+        let clap_version = desc.1.iter().any(|d| matches!(d, Desc::Version(_)));
         let clap = Self::clap(desc, opts);
         let config = HashMap::new();
         // FIXME: default workdir
         let cdir = env::current_dir().unwrap_or_else(|_| From::from("./"));
 
-        App { name, args, clap, config, cdir }
+        let migrations = HashMap::new();
+        let components = Arc::new(Mutex::new(Components::new()));
+        let health = Arc::new(Health::new());
+
+        let startup = Mutex::new(Vec::new());
+
+        App { name, about, args, clap, clap_version, subcommands: Vec::new(), config, cdir,
+              version, migrations, startup, components, health }
+    }
+
+    // `--version` prints its short form unless `Desc::Version` is given
+    pub fn with_version(mut self, version: Version) -> Self {
+        self.clap_version = false;
+        self.version = version;
+        self
+    }
+
+    // the built-in subcommands the app doesn't define itself
+    fn builtins(&self) -> Vec<clap::App<'a, 'a>> {
+        let mut subs = vec![
+            Self::messages(clap::SubCommand::with_name("version"))
+                .about(i18n::tr("about.version", "Prints version information"))
+                .arg(clap::Arg::with_name("verbose").short("v").long("verbose")
+                     .help(i18n::tr("help.version.verbose", "Prints build and commit details")))
+                .arg(clap::Arg::with_name("json").long("json")
+                     .help(i18n::tr("help.version.json", "Prints version information as JSON"))),
+            Self::messages(clap::SubCommand::with_name("explain"))
+                .about(i18n::tr("about.explain", "Explains an error code, e.g. YI-CFG-001"))
+                .arg(clap::Arg::with_name("code").required(true)),
+        ];
+        #[cfg(unix)]
+        subs.extend(vec![
+            Self::messages(clap::SubCommand::with_name("ctl"))
+                .about(i18n::tr("about.ctl", "Sends an admin command to the running process"))
+                .setting(clap::AppSettings::TrailingVarArg)
+                .arg(clap::Arg::with_name("cmd").required(true).multiple(true)),
+            Self::messages(clap::SubCommand::with_name("status"))
+                .about(i18n::tr("about.status", "Prints the health and readiness of the running process"))
+                .arg(clap::Arg::with_name("json").long("json")
+                     .help(i18n::tr("help.status.json", "Prints the report as JSON"))),
+        ]);
+        #[cfg(all(unix, feature = "systemd"))]
+        subs.push(Self::messages(clap::SubCommand::with_name("unit"))
+                  .about(i18n::tr("about.unit", "Prints a systemd service unit for the app"))
+                  .setting(clap::AppSettings::TrailingVarArg)
+                  .arg(clap::Arg::with_name("watchdog").long("watchdog").value_name("SEC")
+                       .help(i18n::tr("help.unit.watchdog", "Sets WatchdogSec= of the unit")))
+                  .arg(clap::Arg::with_name("args").multiple(true)
+                       .help(i18n::tr("help.unit.args", "Arguments of ExecStart="))));
+
+        subs.retain(|s| !self.subcommands.iter().any(|n| n == s.get_name()));
+        subs
+    }
+
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
+    // `Desc::About` of the app, its name without one
+    pub fn about(&self) -> &str {
+        &self.about
+    }
+
+    // upgrade the `Desc::File`s of config section `key` before they are merged
+    pub fn with_migrations(mut self, key: T, migrations: Migrations) -> Self {
+        self.migrations.insert(key, migrations);
+        self
+    }
+
+    // Run `f` in the process that keeps running, before the `daemon` section
+    // drops privileges, e.g. to bind a port below 1024 as root.
+    pub fn on_startup<F>(self, name: &str, f: F) -> Self
+    where F: FnOnce() -> YiResult<()> + Send + 'static
+    {
+        self.startup.lock().unwrap_or_else(|e| e.into_inner()).push((name.to_string(), Box::new(f)));
+        self
+    }
+
+    pub fn with_component<C: Component + 'static>(self, component: C) -> Self {
+        self.components.lock().unwrap_or_else(|e| e.into_inner()).add(component);
+        self
+    }
+
+    // Start the components in dependency order, they are stopped in reverse
+    // order on shutdown. The app is ready afterwards, with `health.bind` in
+    // the app config `/healthz` and `/readyz` are served there.
+    pub fn start(&self) -> YiResult<()> {
+        #[cfg(all(unix, feature = "systemd"))]
+        if systemd::is_active() {
+            shutdown::hook("systemd", Duration::from_secs(1), || { let _ = systemd::stopping(); });
+            systemd::spawn_watchdog(self.health.clone());
+        }
+
+        let mut components = self.components.lock().unwrap_or_else(|e| e.into_inner());
+        if !components.is_empty() {
+            components.start(|section| self.get_config(section.unwrap_or(&self.name)))?;
+
+            let stop = self.components.clone();
+            shutdown::hook("components", shutdown::grace(), move || {
+                let _ = stop.lock().unwrap_or_else(|e| e.into_inner()).stop();
+            });
+            let probe = self.components.clone();
+            self.health.probe(move || probe.lock().unwrap_or_else(|e| e.into_inner()).health());
+        }
+
+        if let Ok(bind) = self.get_arg::<String>("health.bind") {
+            self.health.serve(&bind)?;
+        }
+        self.health.set_ready(true);
+        self.notify_ready()
+    }
+
+    // Ends the startup of a `spawn`ed child, its parent exits with 0 then.
+    // `start` calls it, apps without components call it once they serve.
+    pub fn notify_ready(&self) -> YiResult<()> {
+        #[cfg(unix)]
+        daemon::notify_ready()?;
+        #[cfg(all(unix, feature = "systemd"))]
+        systemd::ready()?;
+        Ok(())
+    }
+
+    // the `STATUS=` line of `systemctl status`, nothing without systemd
+    pub fn notify_status(&self, _status: &str) -> YiResult<()> {
+        #[cfg(all(unix, feature = "systemd"))]
+        systemd::status(_status)?;
+        Ok(())
+    }
+
+    pub fn stop(&self) -> YiResult<()> {
+        self.components.lock().unwrap_or_else(|e| e.into_inner()).stop()
+    }
+
+    pub fn component_health(&self) -> Vec<(String, YiResult<()>)> {
+        self.components.lock().unwrap_or_else(|e| e.into_inner()).health()
+    }
+
+    // components and the app report their status here
+    pub fn health(&self) -> &Arc<Health> {
+        &self.health
     }
 
     pub fn args_into<'de, D: Deserialize<'de>>(&self) -> YiResult<D> {
-        self.args.clone().try_into().to_yierr(Error::CmdArg)
+        self.args.clone().try_into().to_yikind(Error::CmdArg)
     }
 
-    pub fn config(mut self, opts: Opts<'a, T>, keys: &[&str]) -> YiResult<Self> {
-        let matches = self.clap.clone().get_matches();
+    // Exits the process on `--help`, `--version`, a bad command line and the
+    // built-in subcommands, like `clap::App::get_matches`.
+    pub fn config(self, opts: Opts<'a, T>, keys: &[&str]) -> YiResult<Self> {
+        match self.try_config(opts, keys) {
+            Err(e) => match e.kind() {
+                YiErrorKind::Clap(c) => c.exit(),
+                YiErrorKind::Cli(code) => {
+                    let _ = io::stdout().flush();
+                    process::exit(*code)
+                }
+                _ => Err(e),
+            },
+            app => app,
+        }
+    }
+
+    // `config` returning those as `YiErrorKind::Clap` and `YiErrorKind::Cli`
+    // errors instead, `run` prints them and exits with their code.
+    pub fn try_config(mut self, opts: Opts<'a, T>, keys: &[&str]) -> YiResult<Self> {
+        #[cfg(unix)]
+        daemon::claim();
+        let choice = color::scan_args(env::args())?.unwrap_or(ColorChoice::Auto);
+        color::set(choice);
+
+        let short = self.version.short();
+        let builtins = self.builtins();
+        let builtin = |name: &str| builtins.iter().any(|s| s.get_name() == name);
+        let clap = self.clap.clone().subcommands(builtins.clone());
+        let clap = if self.clap_version { clap } else { clap.version(short.as_str()) };
+        let matches = clap
+            .global_setting(clap::AppSettings::ColoredHelp)
+            .global_setting(color::clap_setting(choice))
+            .get_matches_safe()?;
+
+        if let Some(m) = matches.subcommand_matches("version").filter(|_| builtin("version")) {
+            self.print_version(m.is_present("verbose"), m.is_present("json"))?;
+            return Err(YiErrorKind::Cli(exit::OK).into());
+        }
+
+        if let Some(m) = matches.subcommand_matches("explain").filter(|_| builtin("explain")) {
+            let code = m.value_of("code").unwrap_or_default();
+            let text = code::explain(code)
+                .ok_or_else(|| YiError::from(format!("unknown error code {}", code)))?;
+            print!("{}", text);
+            return Err(YiErrorKind::Cli(exit::OK).into());
+        }
+
+        #[cfg(unix)]
+        if let Some(m) = matches.subcommand_matches("ctl").filter(|_| builtin("ctl")) {
+            let cmd: Vec<String> = m.values_of("cmd").into_iter().flatten().map(String::from).collect();
+            print!("{}", admin::call(self.admin_path(), &cmd)?);
+            return Err(YiErrorKind::Cli(exit::OK).into());
+        }
+
+        #[cfg(all(unix, feature = "systemd"))]
+        if let Some(m) = matches.subcommand_matches("unit").filter(|_| builtin("unit")) {
+            let watchdog = m.value_of("watchdog").map(|w| w.parse::<u64>())
+                .transpose().to_yikind(Error::CmdArg).with_field("key", "watchdog")?;
+            let exe = env::current_exe()?;
+            let exec_start = std::iter::once(exe.to_string_lossy().into_owned())
+                .chain(m.values_of("args").into_iter().flatten().map(String::from)).collect();
+            print!("{}", Unit {
+                description: self.about.clone(),
+                exec_start,
+                working_directory: self.cdir.to_string_lossy().into_owned(),
+                watchdog_sec: watchdog,
+            });
+            return Err(YiErrorKind::Cli(exit::OK).into());
+        }
+
+        // exits with FAILURE when the process is down or not ready
+        #[cfg(unix)]
+        if let Some(m) = matches.subcommand_matches("status").filter(|_| builtin("status")) {
+            let json = admin::call(self.admin_path(), &["health".to_string()])?;
+            let report: Report = serde_json::from_str(&json).to_yikind(admin::Error::Connect)?;
+            if m.is_present("json") {
+                print!("{}", json);
+            } else {
+                print!("{}", report);
+            }
+            let code = if report.healthy() && report.ready { exit::OK } else { exit::FAILURE };
+            return Err(YiErrorKind::Cli(code).into());
+        }
 
         for (k, descs) in opts {
             let mut c = Config::default();
@@ -105,13 +393,23 @@ where T: Deref<Target=str> + AsRef<str> + Display + Hash + Eq + Clone,
                                 None
                             };
 
-                            c.merge(config::File::with_name(buf.unwrap_or(path)))
-                                .to_yierr(Error::File)?;
+                            let path = buf.unwrap_or(path);
+                            let merged = match self.migrations.get(k) {
+                                Some(m) => c.merge(m.load(path)),
+                                None    => c.merge(config::File::with_name(path)),
+                            };
+                            merged.to_yikind(Error::File).with_field("path", path)
+                                .with_field("section", &**k)
+                                .hint(i18n::tr("hint.file", "the extension may be left out, \
+                                    toml, json, yaml, hjson and ini are tried"))?;
                         }
 
                         Desc::Env(env) => {
                             c.merge(config::Environment::with_prefix(env))
-                                .to_yierr(Error::Env)?;
+                                .to_yikind(Error::Env)
+                                .with_field("prefix", env)
+                                .hint(i18n::trf("hint.env", "check the {}_* environment variables",
+                                                &[&env.to_uppercase()]))?;
                         }
 
                         _ => (),
@@ -126,14 +424,101 @@ where T: Deref<Target=str> + AsRef<str> + Display + Hash + Eq + Clone,
             }
         }
 
+        // LANG decides the help above, `locale` in the config later messages
+        if let Ok(locale) = self.args.get_str("locale") {
+            i18n::set(Locale::from_name(&locale));
+        }
+        if let Ok(ms) = self.args.get::<u64>("shutdown.grace_ms") {
+            shutdown::set_grace(Duration::from_millis(ms));
+        }
+        self.init_log()?;
+        crash::set_config(&self.args);
+        crash::set_dir(self.state_dir());
         self.spwan()?;
 
         Ok(self)
     }
 
+    // Run `main` and exit the process with the code of its outcome, see
+    // `error::exit` for the codes. Errors are printed to stderr, as one line of
+    // JSON with `--error-format json`, clap help and version output of
+    // `try_config` to stdout.
+    pub fn run<F>(self, main: F) -> !
+    where F: FnOnce(Self) -> YiResult<()>
+    {
+        logger::init(&self.name, self.version.short_hash);
+        crash::install(&self.name, self.version.to_full(), self.state_dir());
+        crash::enter_run();
+        #[cfg(unix)]
+        if let Err(e) = shutdown::install() {
+            log::warn!("no signal handling: {:#}", e);
+        }
+
+        let json = scan_value(env::args(), "--error-format").as_deref() == Some("json");
+
+        let code = match panic::catch_unwind(AssertUnwindSafe(|| main(self))) {
+            Ok(Ok(())) => exit::OK,
+            Ok(Err(e)) => {
+                // the `spwan` parent prints it when startup isn't done yet
+                #[cfg(unix)]
+                let _ = daemon::notify_failed(&e, e.exit_code());
+
+                match e.kind() {
+                    YiErrorKind::Clap(c) if !c.use_stderr() => println!("{}", c.message),
+                    YiErrorKind::Cli(exit::OK) => (),
+                    _ if json => eprintln!("{}", e.report().to_json()),
+                    YiErrorKind::Clap(c) => eprintln!("{}", c.message),
+                    _ => {
+                        log::debug!("{:?}", e);
+                        if !e.inner().is_empty() {
+                            eprintln!("{}", e.render());
+                        }
+                    }
+                }
+                e.exit_code()
+            }
+            Err(_) => exit::PANIC,
+        };
+        shutdown::finish();
+
+        log::logger().flush();
+        let _ = io::stdout().flush();
+        process::exit(code)
+    }
+
+    pub fn print_version(&self, verbose: bool, json: bool) -> YiResult<()> {
+        if json {
+            println!("{}", serde_json::to_string_pretty(&self.version).to_yikind(Error::CmdArg)?);
+        } else if verbose {
+            print!("{}", self.version.to_full());
+        } else {
+            println!("{} {}", self.name, self.version.short());
+        }
+        Ok(())
+    }
+
+    pub fn init_log(&self) -> YiResult<&'static Logger> {
+        let logger = logger::init(&self.name, self.version.short_hash);
+
+        let conf = match self.args.get::<LogConfig>("log") {
+            Err(config::ConfigError::NotFound(_)) => LogConfig::default(),
+            conf => conf.to_yikind(Error::LogConf)?,
+        };
+        logger.configure(&conf)?;
+
+        Ok(logger)
+    }
+
     pub fn spwan(&self) -> YiResult<()> {
         let spawn: bool = self.get_arg("spawn").unwrap_or(false);
 
+        // systemd supervises the process itself
+        #[cfg(all(unix, feature = "systemd"))]
+        if spawn && systemd::is_active() {
+            log::info!("started by systemd, not spawning");
+            return self.harden();
+        }
+
         if env::var(CLONE_SPAWN).ok().is_none() && spawn {
             if let Some(exe) = env::current_exe()?.to_str() {
                 use std::process::{ Command, Stdio };
@@ -141,15 +526,10 @@ where T: Deref<Target=str> + AsRef<str> + Display + Hash + Eq + Clone,
                 let mut log_file = fs::OpenOptions::new();
                 let mut err_file = fs::OpenOptions::new();
 
-                let path = |ext| {
-                    let name = format!("{}/{}{}", "log", &*self.name, ext);
-                    self.filepath(&name)
-                };
-
-                let log_path = path(".log");
-                // let err_path = path(".err");
-                let err_path = path(".log"); // FIXME: error information 
-                let pid_path = path(".pid");
+                let log_path = self.run_path(".log");
+                // let err_path = self.run_path(".err");
+                let err_path = self.run_path(".log"); // FIXME: error information 
+                let pid_path = self.pid_path();
 
                 #[cfg(debug_assertions)]
                 println!("logfile: {:?}, {:?}, {:?}", log_path, err_path, pid_path);
@@ -164,19 +544,48 @@ where T: Deref<Target=str> + AsRef<str> + Display + Hash + Eq + Clone,
                 log_file.write(true).append(true);
                 err_file.write(true).append(true);
 
-                let log_file = log_file.open(log_path).to_yierr(Error::LogFile)?;
-                let err_file = err_file.open(err_path).to_yierr(Error::ErrFile)?;
+                let log_file = log_file.open(&log_path).to_yikind(Error::LogFile)
+                    .with_field("path", &log_path)
+                    .hint(i18n::tr("hint.log", "check that log/ exists and is writable"))?;
+                let err_file = err_file.open(&err_path).to_yikind(Error::ErrFile)
+                    .with_field("path", &err_path)
+                    .hint(i18n::tr("hint.log", "check that log/ exists and is writable"))?;
 
-                let mut _child = Command::new(exe)
-                    .env(CLONE_SPAWN, "")
+                let mut command = Command::new(exe);
+                command.env(CLONE_SPAWN, "")
                     .args(env::args().skip(1))
                     .stdin(Stdio::null())
                     .stdout(log_file)
-                    .stderr(err_file)
-                    .spawn()?;
+                    .stderr(err_file);
+
+                // the parent exits once the child is ready, with its error otherwise
+                #[cfg(unix)]
+                let handshake = Handshake::new()?;
+                #[cfg(unix)]
+                handshake.command(&mut command);
+                let mut child = command.spawn()?;
 
                 #[cfg(windows)]
-                _child.wait()?;
+                child.wait()?;
+
+                #[cfg(unix)]
+                {
+                    let timeout = Duration::from_millis(self.get_arg("spawn_timeout_ms").unwrap_or(30_000));
+                    let (e, code) = match handshake.wait(&mut child, timeout).with_field("log", &log_path)? {
+                        Startup::Ready => (None, exit::OK),
+                        Startup::Failed(e, code) => (Some(e), code),
+                        Startup::Timeout(e) => (Some(e), exit::TEMPFAIL),
+                    };
+                    if let Some(e) = e {
+                        let e = e.with_field("log", &log_path);
+                        if scan_value(env::args(), "--error-format").as_deref() == Some("json") {
+                            eprintln!("{}", e.report().to_json());
+                        } else {
+                            eprintln!("{}", e.render());
+                        }
+                        std::process::exit(code);
+                    }
+                }
 
                 #[cfg(debug_assertions)]
                 println!("parent process exit");
@@ -185,9 +594,77 @@ where T: Deref<Target=str> + AsRef<str> + Display + Hash + Eq + Clone,
             }
         }
 
+        self.harden()
+    }
+
+    // umask and limits, the startup hooks, then chroot and the switch to
+    // `user` and `group` of the `daemon` section
+    fn harden(&self) -> YiResult<()> {
+        #[cfg(unix)]
+        let daemon = match self.args.get::<Hardening>("daemon") {
+            Err(config::ConfigError::NotFound(_)) => Hardening::default(),
+            conf => conf.to_yikind(daemon::Error::Config).with_field("key", "daemon")?,
+        };
+        #[cfg(unix)]
+        daemon.apply_limits()?;
+
+        let hooks = std::mem::take(&mut *self.startup.lock().unwrap_or_else(|e| e.into_inner()));
+        for (name, hook) in hooks {
+            hook().with_field("startup_hook", &name)?;
+        }
+
+        #[cfg(unix)]
+        daemon.drop_privileges()?;
         Ok(())
     }
 
+    // set by SIGTERM, SIGINT, `ctl shutdown` or when `main` returns, without
+    // a token taken the process exits once the shutdown hooks are done
+    pub fn shutdown(&self) -> Shutdown {
+        shutdown::token()
+    }
+
+    // see `shutdown::hook`, hooks run in registration order
+    pub fn on_shutdown<F: FnOnce() + Send + 'static>(&self, name: &str, timeout: Duration, f: F) {
+        shutdown::hook(name, timeout, f)
+    }
+
+    // log/<name><ext>
+    fn run_path(&self, ext: &str) -> PathBuf {
+        self.filepath(&format!("{}/{}{}", "log", &*self.name, ext))
+    }
+
+    pub fn pid_path(&self) -> PathBuf {
+        self.run_path(".pid")
+    }
+
+    // the admin control socket, next to the PID file
+    pub fn admin_path(&self) -> PathBuf {
+        self.run_path(".sock")
+    }
+
+    // Admin socket with the built-in commands, `reload` also applies the
+    // `log` section. Add commands, then `spawn` it.
+    #[cfg(unix)]
+    pub fn admin(&self) -> Admin {
+        Admin::new(self.admin_path(), &self.name, self.version.clone(), self.args.clone())
+            .health(self.health.clone())
+            .on_reload(|config| {
+                let conf = match config.get::<LogConfig>("log") {
+                    Err(config::ConfigError::NotFound(_)) => LogConfig::default(),
+                    conf => conf.to_yikind(Error::LogConf)?,
+                };
+                logger::logger().map_or(Ok(()), |l| l.configure(&conf))
+            })
+    }
+
+    // crash reports and other runtime state, `state_dir` in the app config
+    pub fn state_dir(&self) -> PathBuf {
+        self.get_arg::<String>("state_dir")
+            .map(|dir| self.filepath(&dir))
+            .unwrap_or_else(|_| self.filepath("state"))
+    }
+
     pub fn filepath(&self, name: &str) -> PathBuf {
         if &name[0..1] == "/" {
             PathBuf::from(name)
@@ -202,11 +679,41 @@ where T: Deref<Target=str> + AsRef<str> + Display + Hash + Eq + Clone,
         &self.args
     }
 
+    // config of section `key` of `opts`, the app's own for its name
+    pub fn get_config(&self, key: &str) -> Option<&Config> {
+        if &*self.name == key {
+            Some(&self.args)
+        } else {
+            self.config.iter().find(|(k, _)| &***k == key).map(|(_, c)| c)
+        }
+    }
+
+    // `rpc` in the config of section `key`, defaults when it is missing
+    pub fn rpc_config(&self, key: &str) -> YiResult<RpcConfig> {
+        let config = self.get_config(key)
+            .ok_or_else(|| YiError::from(rpc::Error::Config).with_field("section", key))?;
+        match config.get::<RpcConfig>("rpc") {
+            Err(config::ConfigError::NotFound(_)) => Ok(RpcConfig::default()),
+            conf => conf.to_yikind(rpc::Error::Config).with_field("section", key).with_field("key", "rpc"),
+        }
+    }
+
+    pub fn rpc_server<S: rpc::Service>(&self, key: &str, service: S) -> YiResult<rpc::Server<S>> {
+        rpc::Server::bind(&self.rpc_config(key)?, service)
+    }
+
+    pub fn rpc_client<Req, Resp>(&self, key: &str) -> YiResult<rpc::Client<Req, Resp>>
+    where Req: Serialize, Resp: DeserializeOwned
+    {
+        rpc::Client::connect(&self.rpc_config(key)?)
+    }
+
     pub fn get_arg<'de, D: Deserialize<'de>>(&self, key: &'de str) -> YiResult<D> {
-        self.args.get(key).to_yierr(Error::CmdArg)
+        self.args.get(key).to_yikind(Error::CmdArg).with_field("key", key)
     }
 
     pub fn with_subclap(mut self, subs: &[clap::App<'a, 'a>]) -> Self {
+        self.subcommands.extend(subs.iter().map(|s| s.get_name().to_string()));
         let apps = self.clap.subcommands(subs.to_vec());
         self.clap = apps;
         self
@@ -220,6 +727,13 @@ where T: Deref<Target=str> + AsRef<str> + Display + Hash + Eq + Clone,
         Self::inner_clap(desc, args, true)
     }
 
+    // clap's own help and version flags in the selected locale
+    fn messages(app: clap::App<'a, 'a>) -> clap::App<'a, 'a> {
+        app.help_message(i18n::tr("help.help", "Prints help information"))
+            .version_message(i18n::tr("help.version", "Prints version information"))
+    }
+
+    #[allow(clippy::explicit_auto_deref)]
     fn inner_clap(desc: Opt<'a, T>, args: Opts<'a, T>, subcmd: bool)
                   -> clap::App<'a, 'a> {
         let app = if subcmd {
@@ -227,10 +741,27 @@ where T: Deref<Target=str> + AsRef<str> + Display + Hash + Eq + Clone,
         } else {
             clap::App::new(desc.0.as_ref())
         };
+        let app = Self::messages(app);
+
+        let app = if subcmd {
+            app
+        } else {
+            app.arg(clap::Arg::with_name("color")
+                    .long("color")
+                    .value_name("WHEN")
+                    .takes_value(true)
+                    .help(i18n::tr("help.color", "Coloring: auto, always, never")))
+                .arg(clap::Arg::with_name("error-format")
+                     .long("error-format")
+                     .value_name("FORMAT")
+                     .possible_values(&["human", "json"])
+                     .help(i18n::tr("help.error-format", "Format of errors printed on stderr")))
+        };
 
+        let about = format!("about.{}", desc.0);
         let app = desc.1.iter().fold(app, |app, desc| {
             match desc {
-                Desc::About(v) => app.about(*v),
+                Desc::About(v) => app.about(i18n::tr(&about, v)),
                 Desc::Author(v) => app.author(*v),
                 Desc::Version(v) => app.version(*v),
                 _             => app,
@@ -243,7 +774,7 @@ where T: Deref<Target=str> + AsRef<str> + Display + Hash + Eq + Clone,
             let arg = opts.iter().fold(arg, |arg, desc| {
                 match desc {
                     Desc::Index(v) => arg.index(*v),
-                    Desc::Help(v) => arg.help(*v),
+                    Desc::Help(v) => arg.help(i18n::tr(&format!("help.{}", name), v)),
                     Desc::Short(v) => arg.short(*v),
                     Desc::Long(v) => arg.long(*v),
                     Desc::Long_   => arg.long(name),
@@ -260,7 +791,7 @@ where T: Deref<Target=str> + AsRef<str> + Display + Hash + Eq + Clone,
         })
     }
 
-    fn arg_matches(keys: &[&str], matches: &clap::ArgMatches<'a>, config: &mut Config)
+    fn arg_matches(keys: &[&str], matches: &clap::ArgMatches, config: &mut Config)
                    -> YiResult<()> {
 
         let mut prefix = "";
@@ -275,15 +806,15 @@ where T: Deref<Target=str> + AsRef<str> + Display + Hash + Eq + Clone,
             let v = matches.value_of(k);
             if matches.occurrences_of(k) > 0 {
                 if let Some(v) = v {
-                    config.set(&ck, v).to_yierr(Error::CmdArg)?;
+                    config.set(&ck, v).to_yikind(Error::CmdArg).with_field("key", &ck)?;
                 } else {
-                    config.set(&ck, true).to_yierr(Error::CmdArg)?;
+                    config.set(&ck, true).to_yikind(Error::CmdArg).with_field("key", &ck)?;
                 }
             } else if config.get_str(&ck).is_err() {
                 if let Some(v) = v {
-                    config.set(&ck, v).to_yierr(Error::CmdArg)?;
+                    config.set(&ck, v).to_yikind(Error::CmdArg).with_field("key", &ck)?;
                 } else {
-                    config.set(&ck, false).to_yierr(Error::CmdArg)?;
+                    config.set(&ck, false).to_yikind(Error::CmdArg).with_field("key", &ck)?;
                 }
             }
         }
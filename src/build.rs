@@ -0,0 +1,400 @@
+// Build information for `Version`, shared by yiapp's own build.rs and the
+// build.rs of applications built on it:
+//
+//     // build.rs, with yiapp in [build-dependencies]
+//     fn main() {
+//         yiapp::build::emit();
+//     }
+//
+//     // main.rs
+//     let app = App::new(yiapp::yiapp_version!(), desc, opts);
+//
+// Keep this file free of crate dependencies, build.rs includes it by path.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// CI may set these to override what git would report
+const OVERRIDES: &[&str] = &["SRC_HASH", "SRC_SHORT_HASH", "SRC_DATE", "SRC_BRANCH", "SRC_TAG"];
+
+// Print the `cargo:rustc-env` lines read back by `yiapp_version!()`.
+pub fn emit() {
+    let ci = Commit;
+    let build = Build;
+
+    // rebuild on checkout, on new commits of the current branch and when the
+    // index changes, `SRC_DIRTY` misses edits that git hasn't looked at yet
+    if let Some(dir) = ci.git_dir() {
+        let refs = Commit::exec_git(&["symbolic-ref", "-q", "HEAD"]);
+        for file in &["HEAD", "index", refs.trim()] {
+            let path = Path::new(&dir).join(file);
+            if !file.is_empty() && path.is_file() {
+                println!("cargo:rerun-if-changed={}", path.display());
+            }
+        }
+    }
+    for file in &["Cargo.toml", VCS_INFO, VERSION_FILE] {
+        let path = Build::manifest_dir().join(file);
+        if path.is_file() {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+    for key in OVERRIDES {
+        println!("cargo:rerun-if-env-changed={}", key);
+    }
+
+    let info = ci.info();
+    if info.source.is_empty() {
+        println!("cargo:warning=no git checkout, {}, {} or SRC_HASH for {}, \
+                  commit information will be empty",
+                 VCS_INFO, VERSION_FILE, env::var("CARGO_PKG_NAME").unwrap_or_default());
+    }
+
+    println!("cargo:rerun-if-env-changed=CFG_RELEASE_CHANNEL");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rustc-env=SRC_RELEASE={}", ci.release());
+    println!("cargo:rustc-env=SRC_CHANNEL={}", ci.channel());
+    println!("cargo:rustc-env=SRC_HASH={}", info.hash);
+    println!("cargo:rustc-env=SRC_SHORT_HASH={}", info.short_hash);
+    println!("cargo:rustc-env=SRC_DATE={}", info.date);
+    println!("cargo:rustc-env=SRC_DIRTY={}", info.dirty);
+    println!("cargo:rustc-env=SRC_BRANCH={}", info.branch);
+    println!("cargo:rustc-env=SRC_TAG={}", info.tag);
+    println!("cargo:rustc-env=SRC_SOURCE={}", info.source);
+
+    println!("cargo:rustc-env=SRC_RUSTC={}", build.rustc());
+    println!("cargo:rustc-env=SRC_TARGET={}", build.target());
+    println!("cargo:rustc-env=SRC_PROFILE={}", build.profile());
+    println!("cargo:rustc-env=SRC_FEATURES={}", build.features());
+    println!("cargo:rustc-env=SRC_BUILD_TIME={}", build.time());
+}
+
+// written by `cargo package`, present in crates.io tarballs
+const VCS_INFO: &str = ".cargo_vcs_info.json";
+// `hash = ...`, `date = ...`, `branch = ...`, `tag = ...` lines, or just the hash
+const VERSION_FILE: &str = "VERSION";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Info {
+    // where the commit information came from: env, git, cargo_vcs_info, file
+    pub source: String,
+    pub hash: String,
+    pub short_hash: String,
+    pub date: String,
+    pub dirty: bool,
+    pub branch: String,
+    pub tag: String,
+}
+
+impl Info {
+    fn with_short_hash(mut self) -> Self {
+        if self.short_hash.is_empty() {
+            self.short_hash = self.hash.chars().take(7).collect();
+        }
+        self
+    }
+
+    pub fn from_env() -> Option<Info> {
+        let var = |k| env::var(k).unwrap_or_default().trim().to_string();
+        let hash = var("SRC_HASH");
+        if hash.is_empty() {
+            return None;
+        }
+
+        Some(Info {
+            source: "env".to_string(),
+            hash,
+            short_hash: var("SRC_SHORT_HASH"),
+            date: var("SRC_DATE"),
+            dirty: false,
+            branch: var("SRC_BRANCH"),
+            tag: var("SRC_TAG"),
+        }.with_short_hash())
+    }
+
+    // {"git": {"sha1": "...", "dirty": true}, "path_in_vcs": ""}
+    pub fn from_vcs_info(text: &str) -> Option<Info> {
+        let sha1 = text.split("\"sha1\"").nth(1)?.split('"').nth(1)?;
+        let dirty = text.split("\"dirty\"").nth(1)
+            .is_some_and(|v| v.trim_start_matches([' ', ':']).starts_with("true"));
+
+        Some(Info {
+            source: "cargo_vcs_info".to_string(),
+            hash: sha1.to_string(),
+            dirty,
+            ..Info::default()
+        }.with_short_hash())
+    }
+
+    pub fn from_version_file(text: &str) -> Option<Info> {
+        let mut info = Info { source: "file".to_string(), ..Info::default() };
+
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            match line.split_once('=').map(|(k, v)| (k.trim(), v.trim())) {
+                Some(("hash", v))       => info.hash = v.to_string(),
+                Some(("short_hash", v)) => info.short_hash = v.to_string(),
+                Some(("date", v))       => info.date = v.to_string(),
+                Some(("branch", v))     => info.branch = v.to_string(),
+                Some(("tag", v))        => info.tag = v.to_string(),
+                Some(_)                 => (),
+                None if info.hash.is_empty() => info.hash = line.to_string(),
+                None                    => (),
+            }
+        }
+
+        if info.hash.is_empty() { None } else { Some(info.with_short_hash()) }
+    }
+}
+
+pub struct Commit;
+
+impl Commit {
+    // Get hash and date of the last commit.
+    // If wrong (not git installed or not a git repository) then return an empty string.
+
+    pub fn release(&self) -> String {
+        format!("{}.{}.{}",
+                Build::pkg("CARGO_PKG_VERSION_MAJOR"),
+                Build::pkg("CARGO_PKG_VERSION_MINOR"),
+                Build::pkg("CARGO_PKG_VERSION_PATCH"))
+    }
+
+    pub fn channel(&self) -> String {
+        if let Ok(channel) = env::var("CFG_RELEASE_CHANNEL") {
+            channel
+        } else {
+            "nightly".to_owned()
+        }
+    }
+
+    pub fn hash(&self) -> String {
+        Self::exec_git(&["rev-parse", "HEAD"]).trim().to_string()
+    }
+
+    pub fn short_hash(&self) -> String {
+        Self::exec_git(&["rev-parse", "--short", "HEAD"]).trim().to_string()
+    }
+
+    pub fn date(&self) -> String {
+        Self::exec_git(&["log", "-1", "--date=short", "--pretty=format:%cd"])
+    }
+
+    // tracked files modified, untracked files don't count
+    pub fn dirty(&self) -> bool {
+        !Self::exec_git(&["status", "--porcelain", "--untracked-files=no"]).trim().is_empty()
+    }
+
+    // empty on a detached HEAD
+    pub fn branch(&self) -> String {
+        let branch = Self::exec_git(&["rev-parse", "--abbrev-ref", "HEAD"]);
+        match branch.trim() {
+            "HEAD" => "".to_string(),
+            b      => b.to_string(),
+        }
+    }
+
+    // nearest tag as `git describe` puts it, e.g. v1.2.0-3-g1a2b3c4
+    pub fn tag(&self) -> String {
+        Self::exec_git(&["describe", "--tags"]).trim().to_string()
+    }
+
+    // SRC_* overrides first. A packaged crate may sit inside another checkout
+    // (vendored), so its .cargo_vcs_info.json wins over what git says.
+    pub fn info(&self) -> Info {
+        let file = |name| std::fs::read_to_string(Build::manifest_dir().join(name)).ok();
+
+        Info::from_env()
+            .or_else(|| file(VCS_INFO).and_then(|t| Info::from_vcs_info(&t)))
+            .or_else(|| self.git())
+            .or_else(|| file(VERSION_FILE).and_then(|t| Info::from_version_file(&t)))
+            .unwrap_or_default()
+    }
+
+    pub fn git(&self) -> Option<Info> {
+        let hash = self.hash();
+        if hash.is_empty() {
+            return None;
+        }
+
+        Some(Info {
+            source: "git".to_string(),
+            hash,
+            short_hash: self.short_hash(),
+            date: self.date(),
+            dirty: self.dirty(),
+            branch: self.branch(),
+            tag: self.tag(),
+        })
+    }
+
+    pub fn git_dir(&self) -> Option<String> {
+        let dir = Self::exec_git(&["rev-parse", "--git-dir"]);
+        if dir.trim().is_empty() { None } else { Some(dir.trim().to_string()) }
+    }
+
+    fn exec_git(args: &[&str]) -> String {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|r| r.status.success())
+            .and_then(|r| String::from_utf8(r.stdout).ok())
+            .map_or("".to_string(), |r| r)
+    }
+}
+
+pub struct Build;
+
+impl Build {
+    pub fn rustc(&self) -> String {
+        let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        Command::new(rustc)
+            .arg("--version")
+            .output()
+            .ok()
+            .and_then(|r| String::from_utf8(r.stdout).ok())
+            .map_or("".to_string(), |r| r.trim().to_string())
+    }
+
+    pub fn target(&self) -> String {
+        Self::pkg("TARGET")
+    }
+
+    pub fn profile(&self) -> String {
+        Self::pkg("PROFILE")
+    }
+
+    // enabled cargo features of the package, comma separated, named as in
+    // `[features]` of Cargo.toml
+    pub fn features(&self) -> String {
+        let manifest = std::fs::read_to_string(Self::manifest_dir().join("Cargo.toml"))
+            .unwrap_or_default();
+        let enabled: Vec<String> = env::vars()
+            .filter_map(|(k, _)| k.strip_prefix("CARGO_FEATURE_").map(String::from))
+            .collect();
+        let mut features = feature_names(&manifest, &enabled);
+        features.sort();
+        features.join(",")
+    }
+
+    // RFC 3339 in UTC, SOURCE_DATE_EPOCH for reproducible builds
+    pub fn time(&self) -> String {
+        let secs = env::var("SOURCE_DATE_EPOCH").ok()
+            .and_then(|s| s.trim().parse::<i64>().ok())
+            .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH)
+                            .map(|d| d.as_secs() as i64).unwrap_or(0));
+
+        let (year, month, day) = civil_from_days(secs.div_euclid(86_400));
+        let rem = secs.rem_euclid(86_400);
+        format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                year, month, day, rem / 3600, rem % 3600 / 60, rem % 60)
+    }
+
+    // build scripts see the package being built, not yiapp
+    fn pkg(key: &str) -> String {
+        env::var(key).unwrap_or_default()
+    }
+
+    fn manifest_dir() -> PathBuf {
+        PathBuf::from(Self::pkg("CARGO_MANIFEST_DIR"))
+    }
+}
+
+// Names of the `CARGO_FEATURE_*` suffixes in `enabled`, looked up in the
+// `[features]` table, cargo upper-cases them and turns `-` into `_`.
+// Implicit features of optional dependencies come out in lower case.
+fn feature_names(manifest: &str, enabled: &[String]) -> Vec<String> {
+    let mut table = false;
+    let declared: Vec<&str> = manifest.lines().map(str::trim).filter_map(|line| {
+        if line.starts_with('[') {
+            table = line == "[features]";
+            return None;
+        }
+        match line.split_once('=') {
+            Some((name, _)) if table && !line.starts_with('#') => Some(name.trim().trim_matches('"')),
+            _ => None,
+        }
+    }).collect();
+
+    enabled.iter().map(|var| {
+        declared.iter().find(|name| name.to_uppercase().replace('-', "_") == *var)
+            .map_or_else(|| var.to_lowercase(), |name| name.to_string())
+    }).collect()
+}
+
+// (year, month, day) of days since 1970-01-01,
+// http://howardhinnant.github.io/date_algorithms.html
+pub fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commit() {
+        assert_eq!(Commit.release(), env!("SRC_RELEASE"));
+        // SRC_HASH may be overridden by the environment
+        if env!("SRC_SOURCE") == "git" {
+            assert_eq!(Commit.hash(), env!("SRC_HASH"));
+        }
+        assert!(Commit.hash().starts_with(&Commit.short_hash()));
+    }
+
+    #[test]
+    fn vcs_info() {
+        let info = Info::from_vcs_info(r#"{
+  "git": {
+    "sha1": "1a2b3c4d5e6f7a8b9c0d1e2f3a4b5c6d7e8f9a0b",
+    "dirty": true
+  },
+  "path_in_vcs": ""
+}"#).unwrap();
+        assert_eq!(info.source, "cargo_vcs_info");
+        assert_eq!(info.short_hash, "1a2b3c4");
+        assert!(info.dirty);
+        assert!(Info::from_vcs_info("{}").is_none());
+    }
+
+    #[test]
+    fn version_file() {
+        let info = Info::from_version_file("# built by ci\nhash = 1a2b3c4d5e\ndate = 2019-09-01\n")
+            .unwrap();
+        assert_eq!(info.source, "file");
+        assert_eq!(info.short_hash, "1a2b3c4");
+        assert_eq!(info.date, "2019-09-01");
+
+        let info = Info::from_version_file("1a2b3c4d5e\n").unwrap();
+        assert_eq!(info.hash, "1a2b3c4d5e");
+        assert!(Info::from_version_file("\n").is_none());
+    }
+
+    #[test]
+    fn features() {
+        let manifest = "[dependencies]\nfoo-dep = \"1\"\n\n[features]\n# comment\nfoo_bar = []\n\
+                        \"with-dash\" = [\"foo_bar\"]\n\n[dev-dependencies]\nx = \"1\"\n";
+        let enabled = ["FOO_BAR".to_string(), "WITH_DASH".to_string(), "SERDE".to_string()];
+        assert_eq!(feature_names(manifest, &enabled), ["foo_bar", "with-dash", "serde"]);
+        assert_eq!(env!("SRC_FEATURES").contains("systemd"), cfg!(feature = "systemd"));
+    }
+
+    #[test]
+    fn civil() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(18_140), (2019, 9, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+}
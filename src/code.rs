@@ -0,0 +1,338 @@
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use super::error::{YiError, YiResult};
+
+// Stable identifier of an error, quoted in support tickets instead of the
+// message text, e.g. YI-CFG-001. `explain <code>` prints `explain`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ErrorCode {
+    pub code: &'static str,
+    pub summary: &'static str,
+    pub explain: &'static str,
+}
+
+// prefix reserved for yiapp itself
+pub const RESERVED: &str = "YI-";
+
+pub const GENERIC: &str = "YI-GEN-001";
+
+// configuration errors, `App::run` exits with `exit::CONFIG` on them
+pub const CONFIG: &[&str] = &["YI-CFG-001", "YI-CFG-002", "YI-CFG-003", "YI-CFG-004", "YI-RPC-005",
+                              "YI-CMP-004", "YI-DMN-004", "YI-DMN-005"];
+
+pub const BUILTIN: &[ErrorCode] = &[
+    ErrorCode {
+        code: "YI-GEN-000",
+        summary: "unknown error",
+        explain: "An error without any further classification. Please report it together \
+                  with the log file and the output of `version --verbose`.",
+    },
+    ErrorCode {
+        code: GENERIC,
+        summary: "error",
+        explain: "A generic error, the message and its causes describe what went wrong. \
+                  Errors of the application itself usually carry a more specific code.",
+    },
+    ErrorCode {
+        code: "YI-CLI-001",
+        summary: "invalid --color value",
+        explain: "The --color option accepts `auto`, `always` or `never`. `auto` colors \
+                  output on a terminal, unless NO_COLOR is set; CLICOLOR_FORCE forces \
+                  colors when it is set to anything but 0.",
+    },
+    ErrorCode {
+        code: "YI-CLI-002",
+        summary: "command exited",
+        explain: "The command stopped with the exit code of this error. The message, if \
+                  any, comes from the command.",
+    },
+    ErrorCode {
+        code: "YI-CLI-003",
+        summary: "command line usage",
+        explain: "The command line could not be parsed: an unknown option, a missing \
+                  value or argument. Run the command with --help to see its usage.",
+    },
+    ErrorCode {
+        code: "YI-CLI-004",
+        summary: "invalid option",
+        explain: "An option was given a value the application cannot use.",
+    },
+    ErrorCode {
+        code: "YI-VER-001",
+        summary: "version mismatch",
+        explain: "A version did not satisfy a requirement: a config file written by a \
+                  newer release, persisted state or a peer speaking an incompatible \
+                  protocol. Upgrade the older side or migrate the data.",
+    },
+    ErrorCode {
+        code: "YI-ACK-001",
+        summary: "unknown code",
+        explain: "A number read from a peer, a config file or the command line is not in \
+                  the code table of the named type. Both sides may run releases with \
+                  different tables; upgrade the older one.",
+    },
+    ErrorCode {
+        code: "YI-IO-001",
+        summary: "io error",
+        explain: "Reading or writing a file, socket or pipe failed. The cause tells the \
+                  operating system error, check paths, permissions and free space.",
+    },
+    ErrorCode {
+        code: "YI-IO-002",
+        summary: "file to log",
+        explain: "The log file next to the application could not be opened when \
+                  spawning into the background. Check that the log/ directory exists \
+                  and is writable.",
+    },
+    ErrorCode {
+        code: "YI-IO-003",
+        summary: "file to error",
+        explain: "The error output file could not be opened when spawning into the \
+                  background. Check that the log/ directory exists and is writable.",
+    },
+    ErrorCode {
+        code: "YI-RPC-001",
+        summary: "rpc bind",
+        explain: "The rpc server could not listen on `bind` of its rpc config. A TCP port \
+                  may be in use or need privileges; for `unix:` paths check the directory \
+                  and whether another instance is running.",
+    },
+    ErrorCode {
+        code: "YI-RPC-002",
+        summary: "rpc connect",
+        explain: "The rpc client could not connect to `bind` of its rpc config within \
+                  `connect_timeout_ms`. Check that the server runs and the address.",
+    },
+    ErrorCode {
+        code: "YI-RPC-003",
+        summary: "rpc frame",
+        explain: "A request or response could not be sent, read or decoded: the \
+                  connection broke, timed out after `timeout_ms`, a frame exceeded \
+                  `max_frame` or both sides use different message types.",
+    },
+    ErrorCode {
+        code: "YI-RPC-004",
+        summary: "rpc remote error",
+        explain: "The server answered with an error whose code is unknown here, it is \
+                  kept in the `remote_code` field. Run `explain` on the server side.",
+    },
+    ErrorCode {
+        code: "YI-RPC-005",
+        summary: "rpc configuration",
+        explain: "The config section given to `rpc_server` or `rpc_client` isn't loaded, \
+                  or its `rpc` table doesn't parse. Check the `section` and `key` fields.",
+    },
+    ErrorCode {
+        code: "YI-ADM-001",
+        summary: "admin socket",
+        explain: "The admin control socket next to the PID file, log/<name>.sock, could \
+                  not be created. Check that log/ is writable and no other instance runs.",
+    },
+    ErrorCode {
+        code: "YI-ADM-002",
+        summary: "connecting to the admin socket",
+        explain: "`ctl` could not talk to the admin socket of the running process. Start \
+                  the process first and run `ctl` from the same working directory.",
+    },
+    ErrorCode {
+        code: "YI-ADM-003",
+        summary: "unknown admin command",
+        explain: "The process has no such admin command, `ctl help` lists the built-in \
+                  commands and those of the application.",
+    },
+    ErrorCode {
+        code: "YI-CMP-001",
+        summary: "starting component",
+        explain: "A component of the application failed to start, the `component` field \
+                  names it. Components started before it were stopped again.",
+    },
+    ErrorCode {
+        code: "YI-CMP-002",
+        summary: "stopping component",
+        explain: "A component failed to stop cleanly, the `component` field names it. The \
+                  other components were stopped anyway.",
+    },
+    ErrorCode {
+        code: "YI-CMP-003",
+        summary: "component dependencies",
+        explain: "The components can't be ordered: a name is registered twice, a \
+                  dependency is not registered or dependencies form a cycle.",
+    },
+    ErrorCode {
+        code: "YI-CMP-004",
+        summary: "component config section",
+        explain: "A component asks for a config section the application doesn't load, \
+                  add it to the `opts` given to `App::config`.",
+    },
+    ErrorCode {
+        code: "YI-HLT-001",
+        summary: "health endpoint",
+        explain: "The health endpoint could not listen on `health.bind` of the app config, \
+                  the address may be in use or invalid.",
+    },
+    ErrorCode {
+        code: "YI-SYS-001",
+        summary: "notifying systemd",
+        explain: "A message to the `NOTIFY_SOCKET` systemd passed could not be sent, the \
+                  `socket` field names it.",
+    },
+    ErrorCode {
+        code: "YI-SYS-002",
+        summary: "socket from systemd",
+        explain: "A `systemd:<name>` bind names no socket passed by socket activation, \
+                  `FileDescriptorName=` of the .socket unit sets the names.",
+    },
+    ErrorCode {
+        code: "YI-DMN-001",
+        summary: "startup handshake",
+        explain: "The pipe between a `spawn`ing parent and its child failed, the child may \
+                  still be running, see its log.",
+    },
+    ErrorCode {
+        code: "YI-DMN-002",
+        summary: "child not ready in time",
+        explain: "The child started by `spawn` didn't call `App::notify_ready` or \
+                  `App::start` within `spawn_timeout_ms` (30s by default). It is left \
+                  running and the parent exits with 75 (EX_TEMPFAIL).",
+    },
+    ErrorCode {
+        code: "YI-DMN-003",
+        summary: "child exited during startup",
+        explain: "The child started by `spawn` exited before it was ready without reporting \
+                  an error, e.g. on a signal, see its log for details.",
+    },
+    ErrorCode {
+        code: "YI-DMN-004",
+        summary: "daemon config",
+        explain: "The `daemon` section of the app config is invalid, e.g. `umask` isn't an \
+                  octal string like \"027\".",
+    },
+    ErrorCode {
+        code: "YI-DMN-005",
+        summary: "unknown user or group",
+        explain: "`user` or `group` of the `daemon` section names no account, use a name \
+                  from /etc/passwd or /etc/group or a numeric id.",
+    },
+    ErrorCode {
+        code: "YI-DMN-006",
+        summary: "changing the root directory",
+        explain: "`chroot` of the `daemon` section failed, it needs root and an existing \
+                  directory.",
+    },
+    ErrorCode {
+        code: "YI-DMN-007",
+        summary: "setting a resource limit",
+        explain: "`nofile` or `core_dump` of the `daemon` section could not be applied, \
+                  raising a hard limit needs root or CAP_SYS_RESOURCE.",
+    },
+    ErrorCode {
+        code: "YI-DMN-008",
+        summary: "dropping privileges",
+        explain: "Switching to `user` and `group` or setting `no_new_privs` failed, the \
+                  `step` field names the call. Only root may switch users.",
+    },
+    ErrorCode {
+        code: "YI-CFG-001",
+        summary: "loading from file",
+        explain: "A configuration file given by the application could not be loaded. \
+                  Relative paths are resolved from the working directory; the extension \
+                  may be left out, toml, json, yaml, hjson and ini are tried.",
+    },
+    ErrorCode {
+        code: "YI-CFG-002",
+        summary: "loading from environment",
+        explain: "Configuration from environment variables could not be loaded, a \
+                  variable with the application prefix holds an unusable value.",
+    },
+    ErrorCode {
+        code: "YI-CFG-003",
+        summary: "failed to match command argument",
+        explain: "A command line argument could not be stored in or read from the \
+                  configuration, usually a value of the wrong type.",
+    },
+    ErrorCode {
+        code: "YI-CFG-004",
+        summary: "log configuration",
+        explain: "The `log` section of the configuration is invalid. `level` is one of \
+                  off, error, warn, info, debug, trace and `format` is text or json.",
+    },
+];
+
+static RANGES: RwLock<BTreeMap<&'static str, &'static [ErrorCode]>> = RwLock::new(BTreeMap::new());
+
+// Register the codes of an application, all starting with `prefix`, e.g. "SHOP-DB-".
+pub fn register(prefix: &'static str, codes: &'static [ErrorCode]) -> YiResult<()> {
+    if prefix.is_empty() || prefix.starts_with(RESERVED) {
+        return Err(YiError::from(format!("error code prefix {} is reserved", prefix)));
+    }
+    if let Some(c) = codes.iter().find(|c| !c.code.starts_with(prefix)) {
+        return Err(YiError::from(format!("error code {} is outside of {}", c.code, prefix)));
+    }
+
+    let mut ranges = RANGES.write().unwrap_or_else(|e| e.into_inner());
+    if let Some(other) = ranges.keys().find(|p| p.starts_with(prefix) || prefix.starts_with(*p)) {
+        if *other != prefix {
+            return Err(YiError::from(format!("error code prefix {} overlaps {}", prefix, other)));
+        }
+    }
+    ranges.insert(prefix, codes);
+
+    Ok(())
+}
+
+pub fn lookup(code: &str) -> Option<ErrorCode> {
+    let code = code.trim().to_uppercase();
+    BUILTIN.iter().find(|c| c.code == code).copied().or_else(|| {
+        RANGES.read().unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .filter(|(prefix, _)| code.starts_with(*prefix))
+            .flat_map(|(_, codes)| codes.iter())
+            .find(|c| c.code == code)
+            .copied()
+    })
+}
+
+pub fn explain(code: &str) -> Option<String> {
+    lookup(code).map(|c| format!("{}: {}\n\n{}\n", c.code, c.summary, c.explain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHOP: &[ErrorCode] = &[
+        ErrorCode { code: "SHOP-DB-001", summary: "db down", explain: "The database is down." },
+    ];
+
+    #[test]
+    fn builtin_unique() {
+        for (i, c) in BUILTIN.iter().enumerate() {
+            assert!(c.code.starts_with(RESERVED));
+            assert!(BUILTIN[i + 1..].iter().all(|o| o.code != c.code), "{}", c.code);
+        }
+        assert!(explain("yi-cfg-001").unwrap().starts_with("YI-CFG-001: loading from file"));
+    }
+
+    // `Display` of a coded error translates its text when it is the summary
+    #[test]
+    fn summaries() {
+        use crate::arg::Error as Arg;
+        for e in [Arg::File, Arg::Env, Arg::CmdArg, Arg::LogFile, Arg::ErrFile, Arg::LogConf] {
+            assert_eq!(lookup(e.code()).unwrap().summary, e.to_string());
+        }
+        #[cfg(unix)]
+        assert_eq!(lookup("YI-ADM-002").unwrap().summary, crate::admin::Error::Connect.to_string());
+    }
+
+    #[test]
+    fn app_range() {
+        register("SHOP-DB-", SHOP).unwrap();
+        assert_eq!(lookup("SHOP-DB-001").unwrap().summary, "db down");
+        assert!(lookup("SHOP-DB-002").is_none());
+
+        assert!(register("YI-X-", &[]).is_err());
+        assert!(register("SHOP-", &[]).is_err());
+        assert!(register("SHOP-API-", SHOP).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+use std::env;
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use log::Level;
+
+use super::error::{YiError, YiErrorKind, YiResult};
+
+static CHOICE: AtomicU8 = AtomicU8::new(ColorChoice::Auto as u8);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    Auto = 0,
+    Always = 1,
+    Never = 2,
+}
+
+impl FromStr for ColorChoice {
+    type Err = YiError;
+
+    fn from_str(s: &str) -> YiResult<Self> {
+        match s {
+            "auto"   => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never"  => Ok(ColorChoice::Never),
+            _        => Err(YiError::from(YiErrorKind::ShellColor(s.to_string()))),
+        }
+    }
+}
+
+impl ColorChoice {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorChoice::Auto   => "auto",
+            ColorChoice::Always => "always",
+            ColorChoice::Never  => "never",
+        }
+    }
+
+    // NO_COLOR (https://no-color.org) wins over CLICOLOR_FORCE, both only for `auto`
+    pub fn decide(self, no_color: Option<String>, force: Option<String>, tty: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never  => false,
+            ColorChoice::Auto   => {
+                if no_color.is_some_and(|v| !v.is_empty()) {
+                    false
+                } else if force.is_some_and(|v| !v.is_empty() && v != "0") {
+                    true
+                } else {
+                    tty
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Cyan,
+    Bold,
+    Dim,
+    RedBold,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Red     => "31",
+            Style::Yellow  => "33",
+            Style::Green   => "32",
+            Style::Blue    => "34",
+            Style::Cyan    => "36",
+            Style::Bold    => "1",
+            Style::Dim     => "2",
+            Style::RedBold => "1;31",
+        }
+    }
+
+    pub fn level(level: Level) -> Self {
+        match level {
+            Level::Error => Style::RedBold,
+            Level::Warn  => Style::Yellow,
+            Level::Info  => Style::Green,
+            Level::Debug => Style::Blue,
+            Level::Trace => Style::Dim,
+        }
+    }
+}
+
+pub fn set(choice: ColorChoice) {
+    CHOICE.store(choice as u8, Ordering::Relaxed);
+}
+
+pub fn get() -> ColorChoice {
+    match CHOICE.load(Ordering::Relaxed) {
+        1 => ColorChoice::Always,
+        2 => ColorChoice::Never,
+        _ => ColorChoice::Auto,
+    }
+}
+
+pub fn enabled(stream: Stream) -> bool {
+    let tty = match stream {
+        Stream::Stdout => std::io::stdout().is_terminal(),
+        Stream::Stderr => std::io::stderr().is_terminal(),
+    };
+
+    get().decide(no_color(), force(), tty)
+}
+
+pub fn paint<D: Display>(style: Style, text: D, on: bool) -> String {
+    if on {
+        format!("\x1b[{}m{}\x1b[0m", style.code(), text)
+    } else {
+        text.to_string()
+    }
+}
+
+// Value of a long option before clap has parsed the command line, the last
+// one wins, e.g. `--color` has to be known before clap prints help.
+pub fn scan_value<I: IntoIterator<Item = String>>(args: I, long: &str) -> Option<String> {
+    let mut args = args.into_iter();
+    let mut value = None;
+    let prefix = format!("{}=", long);
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == long {
+            value = args.next();
+        } else if let Some(v) = arg.strip_prefix(&prefix) {
+            value = Some(v.to_string());
+        }
+    }
+
+    value
+}
+
+// `--color` must be known before clap renders help or usage errors
+pub fn scan_args<I: IntoIterator<Item = String>>(args: I) -> YiResult<Option<ColorChoice>> {
+    scan_value(args, "--color").map(|v| v.parse()).transpose()
+}
+
+pub fn clap_setting(choice: ColorChoice) -> clap::AppSettings {
+    match choice {
+        ColorChoice::Always => clap::AppSettings::ColorAlways,
+        ColorChoice::Never  => clap::AppSettings::ColorNever,
+        ColorChoice::Auto   => {
+            let auto = |tty| choice.decide(no_color(), force(), tty);
+            match (auto(false), auto(true)) {
+                (true, _)  => clap::AppSettings::ColorAlways,
+                (_, false) => clap::AppSettings::ColorNever,
+                _          => clap::AppSettings::ColorAuto,
+            }
+        }
+    }
+}
+
+fn no_color() -> Option<String> {
+    env::var("NO_COLOR").ok()
+}
+
+fn force() -> Option<String> {
+    env::var("CLICOLOR_FORCE").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(v: &str) -> Option<String> {
+        Some(v.to_string())
+    }
+
+    #[test]
+    fn choice() {
+        assert_eq!("never".parse::<ColorChoice>().unwrap(), ColorChoice::Never);
+        let e = "sometimes".parse::<ColorChoice>().unwrap_err();
+        match e.kind() {
+            YiErrorKind::ShellColor(v) => assert_eq!(v, "sometimes"),
+            k => panic!("unexpected {:?}", k),
+        }
+    }
+
+    #[test]
+    fn decide() {
+        assert!(ColorChoice::Always.decide(s("1"), None, false));
+        assert!(!ColorChoice::Never.decide(None, s("1"), true));
+        assert!(ColorChoice::Auto.decide(None, None, true));
+        assert!(!ColorChoice::Auto.decide(s("1"), s("1"), true));
+        assert!(ColorChoice::Auto.decide(s(""), s("1"), false));
+        assert!(!ColorChoice::Auto.decide(None, s("0"), false));
+    }
+
+    #[test]
+    fn scan() {
+        let args = |v: &[&str]| v.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(scan_args(args(&["app", "--color", "always"])).unwrap(),
+                   Some(ColorChoice::Always));
+        assert_eq!(scan_args(args(&["app", "--color=never", "x"])).unwrap(),
+                   Some(ColorChoice::Never));
+        assert_eq!(scan_args(args(&["app", "--", "--color=never"])).unwrap(), None);
+        assert!(scan_args(args(&["app", "--color=red"])).is_err());
+    }
+
+    #[test]
+    fn paint_off() {
+        assert_eq!(paint(Style::Red, "x", false), "x");
+        assert_eq!(paint(Style::Red, "x", true), "\x1b[31mx\x1b[0m");
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use config::Config;
+
+use super::error::{YiError, YiErrorKind, YiResult, YiResultExt};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    Start,
+    Stop,
+    Depend,
+    Section,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Start   => write!(f, "starting component"),
+            Error::Stop    => write!(f, "stopping component"),
+            Error::Depend  => write!(f, "component dependencies"),
+            Error::Section => write!(f, "component config section"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Start   => "YI-CMP-001",
+            Error::Stop    => "YI-CMP-002",
+            Error::Depend  => "YI-CMP-003",
+            Error::Section => "YI-CMP-004",
+        }
+    }
+}
+
+impl From<Error> for YiErrorKind {
+    fn from(e: Error) -> YiErrorKind {
+        YiErrorKind::Code(e.code(), e.to_string())
+    }
+}
+
+impl From<Error> for YiError {
+    fn from(e: Error) -> YiError {
+        YiError::from(YiErrorKind::from(e))
+    }
+}
+
+// A database pool, cache or server the app wires up, registered with
+// `App::with_component` and started by `App::start`.
+pub trait Component: Send {
+    fn name(&self) -> &str;
+
+    // the `opts` key of the config it gets, the app's own when None
+    fn section(&self) -> Option<&str> {
+        None
+    }
+
+    // names of the components started before this one
+    fn depends(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn start(&mut self, config: &Config) -> YiResult<()>;
+
+    fn stop(&mut self) -> YiResult<()> {
+        Ok(())
+    }
+
+    fn health(&self) -> YiResult<()> {
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct Components {
+    list: Vec<Box<dyn Component>>,
+    // indices into `list` in start order
+    started: Vec<usize>,
+}
+
+impl Components {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<C: Component + 'static>(&mut self, component: C) {
+        self.list.push(Box::new(component));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    // Dependencies first, otherwise in registration order.
+    pub fn order(&self) -> YiResult<Vec<usize>> {
+        let names: Vec<&str> = self.list.iter().map(|c| c.name()).collect();
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].contains(name) {
+                return Err(YiError::from(Error::Depend).with_field("component", name)
+                           .hint("component names must be unique"));
+            }
+        }
+
+        let mut deps = Vec::new();
+        for c in &self.list {
+            let mut ds = BTreeSet::new();
+            for d in c.depends() {
+                let i = names.iter().position(|n| *n == d).ok_or_else(|| {
+                    YiError::from(Error::Depend).with_field("component", c.name())
+                        .with_field("missing", &d)
+                        .hint("register the missing component with App::with_component")
+                })?;
+                ds.insert(i);
+            }
+            deps.push(ds);
+        }
+
+        let mut order = Vec::new();
+        while order.len() < self.list.len() {
+            let next = (0..self.list.len())
+                .find(|i| !order.contains(i) && deps[*i].iter().all(|d| order.contains(d)));
+            match next {
+                Some(i) => order.push(i),
+                None    => {
+                    let cycle: Vec<&str> = (0..names.len()).filter(|i| !order.contains(i))
+                        .map(|i| names[i]).collect();
+                    return Err(YiError::from(Error::Depend)
+                               .with_field("cycle", cycle.join(", ")));
+                }
+            }
+        }
+
+        Ok(order)
+    }
+
+    // Start in dependency order, `config` gives the config of a section. On
+    // a failure the started ones are stopped again.
+    pub fn start<'c, F>(&mut self, config: F) -> YiResult<()>
+    where F: Fn(Option<&str>) -> Option<&'c Config>
+    {
+        for i in self.order()? {
+            let c = &mut self.list[i];
+            let name = c.name().to_string();
+
+            let started = match config(c.section()) {
+                Some(conf) => c.start(conf).to_yikind(Error::Start),
+                None       => Err(YiError::from(Error::Section)
+                                  .with_field("section", c.section().unwrap_or_default())),
+            };
+
+            if let Err(e) = started {
+                if let Err(stop) = self.stop() {
+                    log::warn!("{}", stop);
+                }
+                return Err(e.with_field("component", name));
+            }
+
+            log::info!("component {} started", name);
+            self.started.push(i);
+        }
+
+        Ok(())
+    }
+
+    // Stop the started ones in reverse order, all of them are stopped, the
+    // first failure is returned.
+    pub fn stop(&mut self) -> YiResult<()> {
+        let mut first = None;
+        while let Some(i) = self.started.pop() {
+            let c = &mut self.list[i];
+            match c.stop().to_yikind(Error::Stop).with_field("component", c.name()) {
+                Ok(())  => log::info!("component {} stopped", c.name()),
+                Err(e)  => {
+                    log::error!("{:#}", e);
+                    first.get_or_insert(e);
+                }
+            }
+        }
+
+        first.map_or(Ok(()), Err)
+    }
+
+    // health of the started components, in start order
+    pub fn health(&self) -> Vec<(String, YiResult<()>)> {
+        self.started.iter().map(|i| {
+            let c = &self.list[*i];
+            (c.name().to_string(), c.health().with_field("component", c.name()))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    struct Fake {
+        name: &'static str,
+        depends: &'static [&'static str],
+        fail: bool,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Component for Fake {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn section(&self) -> Option<&str> {
+            Some("app")
+        }
+
+        fn depends(&self) -> Vec<String> {
+            self.depends.iter().map(|d| d.to_string()).collect()
+        }
+
+        fn start(&mut self, config: &Config) -> YiResult<()> {
+            if self.fail {
+                return Err(YiError::from("refused"));
+            }
+            let n = config.get_int("n").to_yierr("n")?;
+            self.log.lock().unwrap().push(format!("start {} {}", self.name, n));
+            Ok(())
+        }
+
+        fn stop(&mut self) -> YiResult<()> {
+            self.log.lock().unwrap().push(format!("stop {}", self.name));
+            Ok(())
+        }
+    }
+
+    fn components(specs: &[(&'static str, &'static [&'static str], bool)])
+                  -> (Components, Arc<Mutex<Vec<String>>>) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut cs = Components::new();
+        for (name, depends, fail) in specs {
+            cs.add(Fake { name, depends, fail: *fail, log: log.clone() });
+        }
+        (cs, log)
+    }
+
+    fn config() -> Config {
+        let mut c = Config::default();
+        c.set("n", 1).unwrap();
+        c
+    }
+
+    #[test]
+    fn ordered() {
+        let (mut cs, log) = components(&[
+            ("server", &["db", "cache"], false),
+            ("cache", &["db"], false),
+            ("db", &[], false),
+        ]);
+        let conf = config();
+        cs.start(|s| if s == Some("app") { Some(&conf) } else { None }).unwrap();
+        assert_eq!(cs.health().len(), 3);
+        cs.stop().unwrap();
+
+        assert_eq!(*log.lock().unwrap(), ["start db 1", "start cache 1", "start server 1",
+                                          "stop server", "stop cache", "stop db"]);
+    }
+
+    #[test]
+    fn failure_rolls_back() {
+        let (mut cs, log) = components(&[("db", &[], false), ("server", &["db"], true)]);
+        let conf = config();
+        let e = cs.start(|_| Some(&conf)).unwrap_err();
+        assert_eq!(e.code(), "YI-CMP-001");
+        assert_eq!(e.fields()["component"], "server");
+        assert_eq!(*log.lock().unwrap(), ["start db 1", "stop db"]);
+
+        let (mut cs, _) = components(&[("db", &[], false)]);
+        let e = cs.start(|_| None).unwrap_err();
+        assert_eq!(e.code(), "YI-CMP-004");
+        assert_eq!(e.fields()["component"], "db");
+    }
+
+    #[test]
+    fn bad_dependencies() {
+        let (cs, _) = components(&[("a", &["b"], false), ("b", &["a"], false)]);
+        assert_eq!(cs.order().unwrap_err().fields()["cycle"], "a, b");
+
+        let (cs, _) = components(&[("a", &["nope"], false)]);
+        assert_eq!(cs.order().unwrap_err().fields()["missing"], "nope");
+
+        let (cs, _) = components(&[("a", &[], false), ("a", &[], false)]);
+        assert_eq!(cs.order().unwrap_err().code(), "YI-CMP-003");
+    }
+}
@@ -0,0 +1,224 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::panic::{self, PanicHookInfo};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::{Once, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::SystemTime;
+use config::Config;
+use serde_json::Value;
+
+use super::error::{exit, Backtrace, BacktraceStatus, YiError};
+use super::logger;
+#[cfg(unix)]
+use super::daemon;
+
+static CRASH: RwLock<Option<Crash>> = RwLock::new(None);
+static HOOK: Once = Once::new();
+// `App::run` catches a panic of the main thread and exits after the shutdown hooks
+static IN_RUN: AtomicBool = AtomicBool::new(false);
+
+// config keys whose values never end up in a crash report
+const SECRETS: &[&str] = &["pass", "secret", "token", "key", "credential"];
+
+#[derive(Debug, Clone, Default)]
+struct Crash {
+    name: String,
+    version: String,
+    dir: PathBuf,
+    config: Value,
+}
+
+// Install the panic hook: log the panic with a backtrace, write a crash report
+// to `<dir>/crash/` and pass it on to the previous hook. A panic of the main
+// thread exits with `exit::PANIC`, other threads unwind as usual.
+pub fn install(name: &str, version: String, dir: PathBuf) {
+    {
+        let mut crash = CRASH.write().unwrap_or_else(|e| e.into_inner());
+        let config = crash.take().map(|c| c.config).unwrap_or(Value::Null);
+        *crash = Some(Crash { name: name.to_string(), version, dir, config });
+    }
+
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| hook(info, &*previous)));
+    });
+}
+
+pub(crate) fn enter_run() {
+    IN_RUN.store(true, Ordering::Relaxed);
+}
+
+pub fn set_dir(dir: PathBuf) {
+    CRASH.write().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(Crash::default).dir = dir;
+}
+
+pub fn set_config(config: &Config) {
+    CRASH.write().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(Crash::default).config =
+        sanitize(config.clone().try_into().unwrap_or(Value::Null));
+}
+
+fn hook(info: &PanicHookInfo, previous: &(dyn Fn(&PanicHookInfo) + Send + Sync)) {
+    let backtrace = Backtrace::capture();
+    let backtrace = match backtrace.status() {
+        BacktraceStatus::Captured => backtrace.to_string(),
+        _                         => String::new(),
+    };
+    log::error!("{}\n{}", info, backtrace);
+
+    // never block inside the hook, a panic may happen while the lock is held
+    let crash = CRASH.try_read().ok().and_then(|c| c.clone());
+    let mut error = YiError::from(info.to_string());
+    if let Some(crash) = crash {
+        let text = report(&crash, &info.to_string(), &backtrace);
+        match write(&crash.dir, &crash.name, &text) {
+            Ok(path) => {
+                log::error!("crash report written to {}", path.display());
+                error = error.with_field("crash_report", path);
+            }
+            Err(e)   => log::error!("failed to write crash report: {}", e),
+        }
+    }
+    previous(info);
+
+    if thread::current().name() != Some("main") {
+        return;
+    }
+    // a `spwan` parent still waiting prints it
+    #[cfg(unix)]
+    let _ = daemon::notify_failed(&error, exit::PANIC);
+    #[cfg(not(unix))]
+    let _ = error;
+
+    log::logger().flush();
+    if !IN_RUN.load(Ordering::Relaxed) {
+        process::exit(exit::PANIC);
+    }
+}
+
+fn report(crash: &Crash, panic: &str, backtrace: &str) -> String {
+    let thread = thread::current();
+    let args: Vec<String> = env::args().collect();
+
+    let mut out = String::new();
+    out.push_str(&format!("crash report of {}\n\n", crash.name));
+    out.push_str(&format!("time: {}\n", logger::timestamp(SystemTime::now())));
+    out.push_str(&format!("pid: {}\n", process::id()));
+    out.push_str(&format!("thread: {}\n", thread.name().unwrap_or("<unnamed>")));
+    out.push_str(&format!("args: {:?}\n", args));
+    out.push_str(&format!("panic: {}\n\n", panic));
+    out.push_str(&format!("[version]\n{}\n", crash.version));
+    out.push_str(&format!("[config]\n{}\n\n",
+                          serde_json::to_string_pretty(&crash.config).unwrap_or_default()));
+    out.push_str(&format!("[backtrace]\n{}\n", if backtrace.is_empty() {
+        "disabled, set RUST_BACKTRACE=1 to capture"
+    } else {
+        backtrace
+    }));
+
+    out
+}
+
+fn write(dir: &Path, name: &str, text: &str) -> std::io::Result<PathBuf> {
+    let dir = dir.join("crash");
+    fs::create_dir_all(&dir)?;
+
+    let secs = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs()).unwrap_or(0);
+    let path = dir.join(format!("{}-{}-{}.txt", name, secs, process::id()));
+    fs::File::create(&path)?.write_all(text.as_bytes())?;
+
+    Ok(path)
+}
+
+pub(crate) fn sanitize(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(map.into_iter().map(|(k, v)| {
+            let lower = k.to_lowercase();
+            if SECRETS.iter().any(|s| lower.contains(s)) {
+                (k, Value::from("***"))
+            } else {
+                (k, sanitize(v))
+            }
+        }).collect()),
+        Value::Array(vs) => Value::Array(vs.into_iter().map(sanitize).collect()),
+        v => v,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_masked() {
+        let mut c = Config::default();
+        c.set("db.password", "hunter2").unwrap();
+        c.set("db.host", "localhost").unwrap();
+        c.set("api_token", "t").unwrap();
+
+        let v = sanitize(c.try_into().unwrap());
+        assert_eq!(v["db"]["password"], "***");
+        assert_eq!(v["db"]["host"], "localhost");
+        assert_eq!(v["api_token"], "***");
+    }
+
+    #[test]
+    fn report_file() {
+        let dir = env::temp_dir().join(format!("yiapp-crash-{}", process::id()));
+        let crash = Crash {
+            name: "myapp".to_string(),
+            version: "myapp 1.0.0".to_string(),
+            dir: dir.clone(),
+            config: Value::Null,
+        };
+        let text = report(&crash, "boom", "");
+        assert!(text.contains("panic: boom"));
+        assert!(text.contains("[version]\nmyapp 1.0.0"));
+        assert!(text.contains("RUST_BACKTRACE"));
+
+        let path = write(&dir, "myapp", &text).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), text);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    const CHILD: &str = "YIAPP_CRASH_CHILD";
+
+    // The panic hook is process-wide, `hook_installed` runs this in a child
+    // so the other tests never see it.
+    #[test]
+    fn hook_child() {
+        let dir = match env::var_os(CHILD) {
+            Some(dir) => PathBuf::from(dir),
+            None      => return,
+        };
+        let mut c = Config::default();
+        c.set("db.password", "hunter2").unwrap();
+        set_config(&c);
+        install("myapp", "myapp 1.0.0".to_string(), dir);
+
+        let config = CRASH.read().unwrap().as_ref().unwrap().config.clone();
+        assert_eq!(config["db"]["password"], "***");
+
+        // only a panic of the main thread exits
+        assert!(thread::spawn(|| panic!("boom")).join().is_err());
+        println!("unwound");
+    }
+
+    #[test]
+    fn hook_installed() {
+        let dir = env::temp_dir().join(format!("yiapp-hook-{}", process::id()));
+        let out = process::Command::new(env::current_exe().unwrap())
+            .args(["crash::tests::hook_child", "--exact", "--nocapture", "--test-threads=1"])
+            .env(CHILD, &dir)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+        assert!(String::from_utf8_lossy(&out.stdout).contains("unwound"));
+        assert_eq!(fs::read_dir(dir.join("crash")).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,613 @@
+use std::ffi::CString;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::sync::{mpsc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+
+use super::error::{exit, YiError, YiErrorKind, YiResult, YiResultExt};
+use super::rpc::Remote;
+
+// the write end of the startup pipe in a `spwan`ed child
+pub const READY_FD: &str = "__YIAPP_READY_FD__";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    Handshake,
+    Timeout,
+    Startup,
+    Config,
+    User,
+    Chroot,
+    Limit,
+    Privileges,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Handshake  => write!(f, "startup handshake"),
+            Error::Timeout    => write!(f, "child not ready in time"),
+            Error::Startup    => write!(f, "child exited during startup"),
+            Error::Config     => write!(f, "daemon config"),
+            Error::User       => write!(f, "unknown user or group"),
+            Error::Chroot     => write!(f, "changing the root directory"),
+            Error::Limit      => write!(f, "setting a resource limit"),
+            Error::Privileges => write!(f, "dropping privileges"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Handshake  => "YI-DMN-001",
+            Error::Timeout    => "YI-DMN-002",
+            Error::Startup    => "YI-DMN-003",
+            Error::Config     => "YI-DMN-004",
+            Error::User       => "YI-DMN-005",
+            Error::Chroot     => "YI-DMN-006",
+            Error::Limit      => "YI-DMN-007",
+            Error::Privileges => "YI-DMN-008",
+        }
+    }
+}
+
+impl From<Error> for YiErrorKind {
+    fn from(e: Error) -> YiErrorKind {
+        YiErrorKind::Code(e.code(), e.to_string())
+    }
+}
+
+impl From<Error> for YiError {
+    fn from(e: Error) -> YiError {
+        YiError::from(YiErrorKind::from(e))
+    }
+}
+
+// one JSON line from the child
+#[derive(Debug, Serialize, Deserialize)]
+enum Message {
+    Ready,
+    Failed { error: Remote, exit_code: i32 },
+}
+
+pub enum Startup {
+    Ready,
+    // the child's error and the code it exits with
+    Failed(YiError, i32),
+    // no answer in time, the child is left running
+    Timeout(YiError),
+}
+
+// Parent side of the startup pipe, the child gets the write end through
+// `READY_FD` and answers with `notify_ready` or `notify_failed`.
+pub struct Handshake {
+    read: File,
+    write: File,
+}
+
+impl Handshake {
+    pub fn new() -> YiResult<Self> {
+        let mut fds = [0 as libc::c_int; 2];
+        unsafe {
+            // the write end is opened up in the child only, see `command`
+            if cloexec_pipe(&mut fds) != 0 {
+                return Err(io::Error::last_os_error()).to_yikind(Error::Handshake);
+            }
+            Ok(Handshake { read: File::from_raw_fd(fds[0]), write: File::from_raw_fd(fds[1]) })
+        }
+    }
+
+    // pass the write end to the child started by `command`
+    pub fn command(&self, command: &mut Command) {
+        let fd = self.write.as_raw_fd();
+        command.env(READY_FD, fd.to_string());
+        unsafe {
+            command.pre_exec(move || {
+                if libc::fcntl(fd, libc::F_SETFD, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+    }
+
+    // Wait for the child's message. A child exiting without one is ready
+    // when it exits with 0, on a timeout it is left running.
+    pub fn wait(self, child: &mut Child, timeout: Duration) -> YiResult<Startup> {
+        let Handshake { read, write } = self;
+        // EOF once the child is gone
+        drop(write);
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let mut line = String::new();
+            let _ = tx.send(BufReader::new(read).read_line(&mut line).map(|_| line));
+        });
+
+        let line = match rx.recv_timeout(timeout) {
+            Ok(line) => line.to_yikind(Error::Handshake)?,
+            Err(_)   => {
+                return Ok(Startup::Timeout(YiError::from(Error::Timeout)
+                           .with_field("pid", child.id())
+                           .with_field("timeout_ms", timeout.as_millis() as u64)
+                           .hint("the child is still running, it has to call \
+                                  App::notify_ready or App::start; spawn_timeout_ms sets the timeout")));
+            }
+        };
+
+        match serde_json::from_str::<Message>(&line) {
+            Ok(Message::Ready) => Ok(Startup::Ready),
+            Ok(Message::Failed { error, exit_code }) => {
+                let _ = child.wait();
+                Ok(Startup::Failed(YiError::from(error), exit_code))
+            }
+            Err(_) => {
+                let status = child.wait().to_yikind(Error::Handshake)?;
+                match status.code() {
+                    Some(exit::OK) => Ok(Startup::Ready),
+                    code => {
+                        let code = code.unwrap_or(exit::FAILURE);
+                        Ok(Startup::Failed(YiError::from(Error::Startup)
+                                           .with_field("status", status.to_string()), code))
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd",
+          target_os = "dragonfly", target_os = "netbsd", target_os = "openbsd",
+          target_os = "illumos", target_os = "solaris"))]
+unsafe fn cloexec_pipe(fds: &mut [libc::c_int; 2]) -> libc::c_int {
+    libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC)
+}
+
+// no pipe2 on macOS
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd",
+              target_os = "dragonfly", target_os = "netbsd", target_os = "openbsd",
+              target_os = "illumos", target_os = "solaris")))]
+unsafe fn cloexec_pipe(fds: &mut [libc::c_int; 2]) -> libc::c_int {
+    if libc::pipe(fds.as_mut_ptr()) != 0 {
+        return -1;
+    }
+    for fd in fds.iter() {
+        libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+    0
+}
+
+// Take the pipe of a waiting `spwan` parent before startup hooks or
+// components start processes of their own, called first thing by `config`.
+pub fn claim() {
+    pipe();
+}
+
+fn pipe() -> &'static Mutex<Option<File>> {
+    static PIPE: OnceLock<Mutex<Option<File>>> = OnceLock::new();
+    PIPE.get_or_init(|| {
+        let fd = std::env::var(READY_FD).ok().and_then(|fd| fd.parse::<RawFd>().ok())
+            .filter(|fd| unsafe { libc::fcntl(*fd, libc::F_GETFD) } >= 0);
+        // neither the fd nor its number are passed on to grandchildren
+        std::env::remove_var(READY_FD);
+        Mutex::new(fd.map(|fd| unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            File::from_raw_fd(fd)
+        }))
+    })
+}
+
+// the first message wins, later ones are dropped
+fn send(message: &Message) -> YiResult<bool> {
+    let file = pipe().lock().unwrap_or_else(|e| e.into_inner()).take();
+    match file {
+        Some(mut f) => {
+            let line = serde_json::to_string(message).to_yikind(Error::Handshake)?;
+            writeln!(f, "{}", line).to_yikind(Error::Handshake)?;
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+// Tell the waiting `spwan` parent startup is done, false when there is none.
+pub fn notify_ready() -> YiResult<bool> {
+    send(&Message::Ready)
+}
+
+// Hand a startup error to the waiting parent, which prints it and exits with
+// `exit_code`.
+pub fn notify_failed(e: &YiError, exit_code: i32) -> YiResult<bool> {
+    send(&Message::Failed { error: Remote::from(e), exit_code })
+}
+
+// `daemon` section of the app config, applied by `App::spwan` in the
+// process that keeps running:
+//
+//     [daemon]
+//     umask = "027"               # octal
+//     nofile = 65536              # RLIMIT_NOFILE
+//     core_dump = false           # RLIMIT_CORE 0, or up to the hard limit
+//     chroot = "/var/lib/app"
+//     user = "app"                # the group defaults to the user's
+//     group = "app"
+//     no_new_privs = true         # PR_SET_NO_NEW_PRIVS, Linux only
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hardening {
+    pub umask: Option<String>,
+    pub nofile: Option<u64>,
+    pub core_dump: Option<bool>,
+    pub chroot: Option<String>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub no_new_privs: bool,
+}
+
+impl Hardening {
+    pub fn is_empty(&self) -> bool {
+        *self == Hardening::default()
+    }
+
+    // umask and resource limits, before the startup hooks so they apply to
+    // what they open
+    pub fn apply_limits(&self) -> YiResult<()> {
+        if let Some(mask) = &self.umask {
+            let mask = u32::from_str_radix(mask.trim_start_matches("0o"), 8).ok()
+                .filter(|m| *m <= 0o777)
+                .ok_or_else(|| YiError::from(Error::Config).with_field("umask", mask)
+                            .hint("an octal mode as a string, e.g. umask = \"027\""))?;
+            unsafe { libc::umask(mask as libc::mode_t) };
+        }
+
+        if let Some(n) = self.nofile {
+            let n = n as libc::rlim_t;
+            setrlimit(libc::RLIMIT_NOFILE, "nofile", |l| { l.rlim_cur = n; l.rlim_max = l.rlim_max.max(n); })
+                .hint("raising the hard limit needs root or CAP_SYS_RESOURCE")?;
+        }
+
+        match self.core_dump {
+            Some(false) => setrlimit(libc::RLIMIT_CORE, "core_dump", |l| { l.rlim_cur = 0; l.rlim_max = 0; })?,
+            Some(true)  => setrlimit(libc::RLIMIT_CORE, "core_dump", |l| l.rlim_cur = l.rlim_max)?,
+            None        => (),
+        }
+        Ok(())
+    }
+
+    // chroot, group and user, no_new_privs, after the startup hooks bound
+    // their ports. Users are looked up before the chroot hides /etc.
+    pub fn drop_privileges(&self) -> YiResult<()> {
+        let user = self.user.as_deref().map(lookup_user).transpose()?;
+        let gid = match (&self.group, user) {
+            (Some(g), _)             => Some(lookup_group(g)?),
+            (None, Some((_, None)))  => return Err(YiError::from(Error::User)
+                                                  .with_field("user", self.user.as_deref())
+                                                  .hint("the uid has no passwd entry, set group")),
+            (None, user)             => user.and_then(|(_, gid)| gid),
+        };
+
+        // supplementary groups of `user`, before the chroot hides /etc/group,
+        // only root may set them
+        if let (Some(gid), true) = (gid, unsafe { libc::geteuid() } == 0) {
+            let rc = match (&self.user, user) {
+                (Some(name), Some((_, Some(_)))) => {
+                    let name = cstring(name, "user")?;
+                    initgroups(&name, gid)
+                }
+                _ => unsafe { libc::setgroups(1, &gid) },
+            };
+            if rc != 0 {
+                return privileges("setgroups", gid);
+            }
+        }
+
+        if let Some(dir) = &self.chroot {
+            let path = cstring(dir, "chroot")?;
+            let root = CString::new("/").unwrap_or_default();
+            unsafe {
+                if libc::chdir(path.as_ptr()) != 0 || libc::chroot(path.as_ptr()) != 0
+                    || libc::chdir(root.as_ptr()) != 0 {
+                    return Err(io::Error::last_os_error()).to_yikind(Error::Chroot)
+                        .with_field("chroot", dir)
+                        .hint("chroot needs root, paths of the app are relative to it afterwards");
+                }
+            }
+            log::info!("chroot to {}", dir);
+        }
+
+        if let Some(gid) = gid {
+            unsafe {
+                if libc::setgid(gid) != 0 {
+                    return privileges("setgid", gid);
+                }
+            }
+        }
+
+        if let Some((uid, _)) = user {
+            unsafe {
+                if libc::setuid(uid) != 0 {
+                    return privileges("setuid", uid);
+                }
+                // never back to root
+                if uid != 0 && libc::setuid(0) == 0 {
+                    return Err(YiError::from(Error::Privileges).with_field("step", "setuid")
+                               .with_field("uid", uid).hint("root could be regained"));
+                }
+            }
+            log::info!("running as {} ({}:{})", self.user.as_deref().unwrap_or_default(),
+                       uid, gid.unwrap_or_default());
+        }
+
+        if self.no_new_privs {
+            no_new_privs()?;
+        }
+        Ok(())
+    }
+}
+
+// the base group is an int on Apple targets
+#[cfg(target_vendor = "apple")]
+fn initgroups(user: &CString, gid: libc::gid_t) -> libc::c_int {
+    unsafe { libc::initgroups(user.as_ptr(), gid as libc::c_int) }
+}
+
+#[cfg(not(target_vendor = "apple"))]
+fn initgroups(user: &CString, gid: libc::gid_t) -> libc::c_int {
+    unsafe { libc::initgroups(user.as_ptr(), gid) }
+}
+
+fn setrlimit<F>(resource: Resource, key: &str, f: F) -> YiResult<()>
+where F: FnOnce(&mut libc::rlimit)
+{
+    let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+    unsafe {
+        if libc::getrlimit(resource, &mut limit) == 0 {
+            f(&mut limit);
+            if libc::setrlimit(resource, &limit) == 0 {
+                return Ok(());
+            }
+        }
+    }
+    Err(io::Error::last_os_error()).to_yikind(Error::Limit)
+        .with_field("key", key)
+        .with_field("limit", limit.rlim_cur)
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+fn privileges(step: &str, id: u32) -> YiResult<()> {
+    Err(io::Error::last_os_error()).to_yikind(Error::Privileges)
+        .with_field("step", step)
+        .with_field("id", id)
+        .hint("switching users needs root, start the app as root or leave out user and group")
+}
+
+#[cfg(target_os = "linux")]
+fn no_new_privs() -> YiResult<()> {
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error()).to_yikind(Error::Privileges)
+            .with_field("step", "no_new_privs");
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn no_new_privs() -> YiResult<()> {
+    Err(YiError::from(Error::Privileges).with_field("step", "no_new_privs")
+        .hint("no_new_privs is Linux only"))
+}
+
+fn cstring(s: &str, key: &str) -> YiResult<CString> {
+    CString::new(s).to_yikind(Error::Config).with_field(key, s)
+}
+
+// Run a `getpwnam_r` style lookup with a buffer grown until it fits, true
+// when `name` was found.
+fn lookup<F>(key: &str, name: &str, mut f: F) -> YiResult<bool>
+where F: FnMut(&mut [libc::c_char]) -> (libc::c_int, bool)
+{
+    let mut buf = vec![0 as libc::c_char; 16 << 10];
+    loop {
+        match f(&mut buf) {
+            (libc::ERANGE, _) if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+            (0, found) => return Ok(found),
+            (rc, _) => return Err(io::Error::from_raw_os_error(rc)).to_yikind(Error::User)
+                .with_field(key, name),
+        }
+    }
+}
+
+// uid and primary gid of `name`, a numeric uid may have no passwd entry
+fn lookup_user(name: &str) -> YiResult<(libc::uid_t, Option<libc::gid_t>)> {
+    let cname = cstring(name, "user")?;
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let found = lookup("user", name, |buf| {
+        let mut found = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getpwnam_r(cname.as_ptr(), &mut pwd, buf.as_mut_ptr(), buf.len(), &mut found)
+        };
+        (rc, !found.is_null())
+    })?;
+    if found {
+        return Ok((pwd.pw_uid, Some(pwd.pw_gid)));
+    }
+    match name.parse::<libc::uid_t>() {
+        Ok(uid) => Ok((uid, None)),
+        Err(_)  => Err(YiError::from(Error::User).with_field("user", name)
+                       .hint("the user has to exist in /etc/passwd or be a numeric uid")),
+    }
+}
+
+fn lookup_group(name: &str) -> YiResult<libc::gid_t> {
+    let cname = cstring(name, "group")?;
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    let found = lookup("group", name, |buf| {
+        let mut found = std::ptr::null_mut();
+        let rc = unsafe {
+            libc::getgrnam_r(cname.as_ptr(), &mut grp, buf.as_mut_ptr(), buf.len(), &mut found)
+        };
+        (rc, !found.is_null())
+    })?;
+    if found {
+        return Ok(grp.gr_gid);
+    }
+    name.parse::<libc::gid_t>().to_yikind(Error::User).with_field("group", name)
+        .hint("the group has to exist in /etc/group or be a numeric gid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process::{self, Stdio};
+
+    const CHILD: &str = "YIAPP_DAEMON_CHILD";
+
+    // The child side of `handshake` and `hardening`, runs only when started
+    // by them.
+    #[test]
+    fn child() {
+        let mode = match env::var(CHILD) {
+            Ok(mode) => mode,
+            Err(_)   => return,
+        };
+
+        match mode.as_str() {
+            "ready" => {
+                assert!(notify_ready().unwrap());
+                assert!(!notify_ready().unwrap());
+                thread::sleep(Duration::from_secs(60));
+            }
+            "fail"  => {
+                let e = YiError::from("port in use").with_field("bind", ":80");
+                notify_failed(&e, exit::CONFIG).unwrap();
+                process::exit(exit::CONFIG);
+            }
+            "exit"  => process::exit(3),
+            "subprocess" => {
+                claim();
+                assert!(env::var(READY_FD).is_err());
+                // outlives the child, holding the pipe open would keep the parent waiting
+                Command::new("sleep").arg("5").stdout(Stdio::null()).spawn().unwrap();
+                process::exit(exit::OK);
+            }
+            "harden" => {
+                let root = unsafe { libc::geteuid() } == 0;
+                let daemon = Hardening {
+                    umask: Some("077".to_string()),
+                    nofile: Some(256),
+                    core_dump: Some(false),
+                    user: if root { Some("nobody".to_string()) } else { None },
+                    no_new_privs: cfg!(target_os = "linux"),
+                    ..Hardening::default()
+                };
+                daemon.apply_limits().unwrap();
+                daemon.drop_privileges().unwrap();
+
+                let mut limit = libc::rlimit { rlim_cur: 0, rlim_max: 0 };
+                unsafe { libc::getrlimit(libc::RLIMIT_CORE, &mut limit) };
+                let mask = unsafe { libc::umask(0) };
+                let mut groups = [0 as libc::gid_t; 64];
+                let n = unsafe { libc::getgroups(64, groups.as_mut_ptr()) };
+                let wheel = root && groups[..n.max(0) as usize].contains(&0);
+                println!("hardened root={} uid={} wheel={} umask={:o} core={}", root,
+                         unsafe { libc::getuid() }, wheel, mask, limit.rlim_cur);
+                process::exit(exit::OK);
+            }
+            _       => thread::sleep(Duration::from_secs(60)),
+        }
+    }
+
+    fn spawn(mode: &str) -> (Child, Handshake) {
+        let handshake = Handshake::new().unwrap();
+        let mut command = Command::new(env::current_exe().unwrap());
+        command.args(["daemon::tests::child", "--exact", "--nocapture", "--test-threads=1"])
+            .env(CHILD, mode)
+            .stdout(Stdio::null());
+        handshake.command(&mut command);
+        let child = command.spawn().unwrap();
+        (child, handshake)
+    }
+
+    #[test]
+    fn handshake() {
+        let timeout = Duration::from_secs(30);
+
+        let (mut child, h) = spawn("ready");
+        assert!(matches!(h.wait(&mut child, timeout).unwrap(), Startup::Ready));
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        let (mut child, h) = spawn("fail");
+        match h.wait(&mut child, timeout).unwrap() {
+            Startup::Failed(e, code) => {
+                assert_eq!(code, exit::CONFIG);
+                assert_eq!(e.inner(), "port in use");
+                assert_eq!(e.fields()["bind"], ":80");
+            }
+            _ => panic!("not failed"),
+        }
+
+        let (mut child, h) = spawn("exit");
+        match h.wait(&mut child, timeout).unwrap() {
+            Startup::Failed(e, code) => assert_eq!((e.code(), code), ("YI-DMN-003", 3)),
+            _ => panic!("not failed"),
+        }
+
+        let start = std::time::Instant::now();
+        let (mut child, h) = spawn("subprocess");
+        assert!(matches!(h.wait(&mut child, Duration::from_secs(3)).unwrap(), Startup::Ready));
+        assert!(start.elapsed() < Duration::from_secs(3));
+
+        let (mut child, h) = spawn("hang");
+        match h.wait(&mut child, Duration::from_millis(300)).unwrap() {
+            Startup::Timeout(e) => assert_eq!(e.fields()["pid"], child.id()),
+            _ => panic!("no timeout"),
+        }
+        assert!(child.try_wait().unwrap().is_none());
+        child.kill().unwrap();
+        child.wait().unwrap();
+    }
+
+    #[test]
+    fn hardening() {
+        let mut c = config::Config::default();
+        c.set("daemon.umask", "027").unwrap();
+        c.set("daemon.user", "app").unwrap();
+        let daemon: Hardening = c.get("daemon").unwrap();
+        assert_eq!(daemon.umask.as_deref(), Some("027"));
+        assert!(!daemon.is_empty() && Hardening::default().is_empty());
+
+        let bad = Hardening { umask: Some("999".to_string()), ..Hardening::default() };
+        assert_eq!(bad.apply_limits().unwrap_err().code(), "YI-DMN-004");
+        let bad = Hardening { user: Some("no-such-user-yiapp".to_string()), ..Hardening::default() };
+        let e = bad.drop_privileges().unwrap_err();
+        assert_eq!((e.code(), e.fields()["user"].as_str()), ("YI-DMN-005", Some("no-such-user-yiapp")));
+        let bad = Hardening { user: Some("4242424".to_string()), ..Hardening::default() };
+        assert_eq!(bad.drop_privileges().unwrap_err().code(), "YI-DMN-005");
+
+        let out = Command::new(env::current_exe().unwrap())
+            .args(["daemon::tests::child", "--exact", "--nocapture", "--test-threads=1"])
+            .env(CHILD, "harden")
+            .output()
+            .unwrap();
+        let out = String::from_utf8_lossy(&out.stdout);
+        let line = out.lines().find(|l| l.contains("hardened")).unwrap();
+        assert!(line.ends_with("umask=77 core=0"), "{}", line);
+        if line.contains("root=true") {
+            assert!(!line.contains("uid=0 ") && line.contains("wheel=false"), "{}", line);
+        }
+    }
+}
@@ -1,8 +1,19 @@
 use std::fmt;
 use std::fmt::Display;
+use std::error::Error as StdError;
+use std::any::Any;
+use std::collections::BTreeMap;
 use log::Level;
+use serde::Serialize;
+use serde_json::Value;
 
-pub use failure::{Backtrace, Context, Error, Fail, ResultExt};
+use super::color::{self, Stream, Style};
+use super::code;
+use super::i18n;
+
+pub use std::backtrace::{Backtrace, BacktraceStatus};
+
+pub type Error = Box<dyn StdError + Send + Sync + 'static>;
 
 pub type _YiResult<T> = Result<T, Error>;
 
@@ -25,74 +36,314 @@ macro_rules! yierr_info { () => (|e| { info!("{}", e); } ); }
 #[macro_export]
 macro_rules! yimap_err { () => (|e| { error!("{}", e); e} ); }
 
-#[derive(Debug, Fail)]
+// Process exit codes of `App::run`, following sysexits(3) where one fits.
+pub mod exit {
+    // success, also `--help` and `--version`
+    pub const OK: i32 = 0;
+    // any error without a more specific code
+    pub const FAILURE: i32 = 1;
+    // bad command line, reported by clap or `--color`
+    pub const USAGE: i32 = 2;
+    // EX_IOERR, an `std::io::Error` in the cause chain
+    pub const IO: i32 = 74;
+    // EX_TEMPFAIL, a `spwan`ed child not ready in time, it is left running
+    pub const TEMPFAIL: i32 = 75;
+    // EX_CONFIG, a configuration file or environment failed to load
+    pub const CONFIG: i32 = 78;
+    // a panic, reported by the crash hook, same code as an uncaught rust panic
+    pub const PANIC: i32 = 101;
+}
+
+#[derive(Debug)]
 pub enum YiErrorKind {
-    #[fail(display = "{}", _0)]
     Info(String),
 
-    #[fail(display = "{}", _0)]
     InfoStr(&'static str),
 
-    #[fail(
-        display = "shell color argument for --color must be auto, always, or \
-                   never, but value: {}",
-        _0
-    )]
     ShellColor(String),
 
-    #[fail(display = "")]
     Cli(i32),
 
-    #[fail(display = "{}", _0)]
     Clap(clap::Error),
 
-    #[fail(display = "{}", _0)]
     Opt(String),
 
-    #[fail(display = "version {}", _0)]
-    Ver(u32),
+    Ver { found: String, required: String },
+
+    // a value missing from the `yiack!` table of `ty`
+    Ack { ty: &'static str, value: String },
 
-    #[fail(display = "io error")]
     StdIo,
 
-    #[fail(display = "An unknown error kind has occurred.")]
     Unknown,
+
+    // error with a code from `code::register`, or of `arg::Error`
+    Code(&'static str, String),
+}
+
+impl YiErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            YiErrorKind::Info(_) | YiErrorKind::InfoStr(_) => code::GENERIC,
+            YiErrorKind::ShellColor(_) => "YI-CLI-001",
+            YiErrorKind::Cli(_)        => "YI-CLI-002",
+            YiErrorKind::Clap(_)       => "YI-CLI-003",
+            YiErrorKind::Opt(_)        => "YI-CLI-004",
+            YiErrorKind::Ver { .. }    => "YI-VER-001",
+            YiErrorKind::Ack { .. }    => "YI-ACK-001",
+            YiErrorKind::StdIo         => "YI-IO-001",
+            YiErrorKind::Unknown       => "YI-GEN-000",
+            YiErrorKind::Code(code, _) => code,
+        }
+    }
+
+    // the variant, `kind` of the JSON report
+    pub fn name(&self) -> &'static str {
+        match self {
+            YiErrorKind::Info(_)       => "Info",
+            YiErrorKind::InfoStr(_)    => "InfoStr",
+            YiErrorKind::ShellColor(_) => "ShellColor",
+            YiErrorKind::Cli(_)        => "Cli",
+            YiErrorKind::Clap(_)       => "Clap",
+            YiErrorKind::Opt(_)        => "Opt",
+            YiErrorKind::Ver { .. }    => "Ver",
+            YiErrorKind::Ack { .. }    => "Ack",
+            YiErrorKind::StdIo         => "StdIo",
+            YiErrorKind::Unknown       => "Unknown",
+            YiErrorKind::Code(..)      => "Code",
+        }
+    }
 }
 
+// fixed texts are looked up by code in the selected locale, see `i18n`
+impl Display for YiErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = self.code();
+        match self {
+            YiErrorKind::Info(s)       => write!(f, "{}", s),
+            YiErrorKind::InfoStr(s)    => write!(f, "{}", s),
+            YiErrorKind::ShellColor(s) => write!(f, "{}", i18n::trf(code,
+                "shell color argument for --color must be auto, always, or never, but value: {}",
+                &[s])),
+            YiErrorKind::Cli(_)        => Ok(()),
+            YiErrorKind::Clap(e)       => write!(f, "{}", e),
+            YiErrorKind::Opt(s)        => write!(f, "{}", s),
+            YiErrorKind::Ver { found, required } =>
+                write!(f, "{}", i18n::trf(code, "version {} does not satisfy {}",
+                                          &[found, required])),
+            YiErrorKind::Ack { ty, value } =>
+                write!(f, "{}", i18n::trf(code, "unknown {} code {}", &[ty, value])),
+            YiErrorKind::StdIo         => write!(f, "{}", i18n::tr(code, "io error")),
+            YiErrorKind::Unknown       =>
+                write!(f, "{}", i18n::tr(code, "An unknown error kind has occurred.")),
+            YiErrorKind::Code(_, s)    => write!(f, "{}", coded(i18n::get(), code, s)),
+        }
+    }
+}
+
+// The title of `code` in `locale` for its fixed English text, followed by
+// `s` when it says more, e.g. the message of a remote error.
+fn coded(locale: i18n::Locale, code: &str, s: &str) -> String {
+    let title = match i18n::lookup(locale, code) {
+        Some(title) => title,
+        None        => return s.to_string(),
+    };
+    if s.is_empty() || code::lookup(code).is_some_and(|c| c.summary == s) {
+        title.to_string()
+    } else {
+        format!("{}: {}", title, s)
+    }
+}
+
+impl StdError for YiErrorKind {}
+
 #[derive(Debug)]
 pub struct YiError {
-    inner: Context<YiErrorKind>,
+    kind: YiErrorKind,
+    source: Option<Error>,
+    detail: Box<Detail>,
 }
 
-impl Fail for YiError {
-    fn cause(&self) -> Option<&dyn Fail> {
-        self.inner.cause()
-    }
+// boxed to keep YiResult small
+#[derive(Debug)]
+struct Detail {
+    backtrace: Backtrace,
+    fields: BTreeMap<String, Value>,
+    hints: Vec<String>,
+}
 
-    fn backtrace(&self) -> Option<&Backtrace> {
-        self.inner.backtrace()
+impl StdError for YiError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_ref().map(|e| &**e as &(dyn StdError + 'static))
     }
 }
 
+// The error itself only, its causes are in `source()`, `chain()` and
+// `render()`. `{:#}` adds them, e.g. for a log line.
 impl Display for YiError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.inner())
+        if f.alternate() {
+            return write!(f, "[{}] {}", self.code(), self.inner());
+        }
+        // a foreign error wrapped by `hint` or `with_field` has no text of its own
+        match self.kind.to_string() {
+            own if own.is_empty() => write!(f, "[{}] {}", self.code(),
+                                            self.chain().first().map_or("", String::as_str)),
+            own                   => write!(f, "[{}] {}", self.code(), own),
+        }
     }
 }
 
 impl YiError {
+    pub fn new<E>(kind: YiErrorKind, source: E) -> Self
+    where E: StdError + Send + Sync + 'static
+    {
+        YiError { source: Some(Box::new(source)), ..YiError::from(kind) }
+    }
+
+    // Record what was involved, e.g. `.with_field("path", &path)`, shown
+    // below the message and in the `context` of the JSON report.
+    pub fn with_field<V: Serialize>(mut self, key: &str, value: V) -> Self {
+        let value = serde_json::to_value(value).unwrap_or_else(|e| Value::String(e.to_string()));
+        self.detail.fields.insert(key.to_string(), value);
+        self
+    }
+
+    // suggest a fix to the user, e.g. `.hint("check permissions on log/")`
+    pub fn hint<H: Display>(mut self, hint: H) -> Self {
+        self.detail.hints.push(hint.to_string());
+        self
+    }
+
+    // fields of this error and the YiErrors it wraps, the outer one wins
+    pub fn fields(&self) -> BTreeMap<String, Value> {
+        let mut fields = BTreeMap::new();
+        for e in self.yierrs().iter().rev() {
+            fields.extend(e.detail.fields.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        fields
+    }
+
+    // hints of this error and the YiErrors it wraps, outermost first
+    pub fn hints(&self) -> Vec<String> {
+        let mut hints: Vec<String> = Vec::new();
+        for h in self.yierrs().iter().flat_map(|e| e.detail.hints.iter()) {
+            if !hints.contains(h) {
+                hints.push(h.clone());
+            }
+        }
+        hints
+    }
+
+    fn yierrs(&self) -> Vec<&YiError> {
+        let mut yierrs = vec![self];
+        let mut err = self.source();
+        while let Some(e) = err {
+            yierrs.extend(e.downcast_ref::<YiError>());
+            err = e.source();
+        }
+        yierrs
+    }
+
+    // an error of any type as a YiError, a YiError itself stays as it is
+    fn wrap<E: StdError + Send + Sync + 'static>(e: E) -> Self {
+        let any: Box<dyn Any> = Box::new(e);
+        match any.downcast::<YiError>() {
+            Ok(yierr) => *yierr,
+            Err(any)  => match any.downcast::<E>() {
+                Ok(e)  => YiError::new(YiErrorKind::Info(String::new()), *e),
+                Err(_) => YiError::from(YiErrorKind::Unknown),
+            },
+        }
+    }
+
     #[inline]
     pub fn inner(&self) -> String {
-        Self::_inner(&self.inner)
+        self.chain().join(", ")
     }
 
     pub fn kind(&self) -> &YiErrorKind {
-        self.inner.get_context()
+        &self.kind
+    }
+
+    // the most specific code in the chain, a generic `to_yierr` context
+    // doesn't hide the code of the error it wraps
+    pub fn code(&self) -> &'static str {
+        let mut err: Option<&(dyn StdError + 'static)> = Some(self);
+        while let Some(e) = err {
+            if let Some(code) = e.downcast_ref::<YiError>().map(|y| y.kind.code())
+                .filter(|c| !c.starts_with("YI-GEN-")) {
+                return code;
+            }
+            err = e.source();
+        }
+
+        self.kind.code()
+    }
+
+    // captured when RUST_BACKTRACE or RUST_LIB_BACKTRACE is set
+    pub fn backtrace(&self) -> &Backtrace {
+        &self.detail.backtrace
     }
 
-    fn _inner_print<T: Display + Fail>(thing: Option<T>, level: Level) {
+    // messages of this error and its sources, a nested YiError adds its own
+    // kind only, not its whole chain again, empty ones are left out
+    pub fn chain(&self) -> Vec<String> {
+        Self::_chain(self)
+    }
+
+    // human readable form for stderr, honours --color
+    pub fn render(&self) -> String {
+        let on = color::enabled(Stream::Stderr);
+        let causes = self.chain();
+
+        let prefix = format!("{}[{}]:", i18n::tr("error", "error"), self.code());
+        let mut text = format!("{} {}", color::paint(Style::RedBold, prefix, on), causes.join(", "));
+
+        for (k, v) in self.fields() {
+            match v {
+                Value::String(s) => text.push_str(&format!("\n  {}: {}", k, s)),
+                v                => text.push_str(&format!("\n  {}: {}", k, v)),
+            }
+        }
+        for h in self.hints() {
+            text.push_str(&format!("\n  {} {}", color::paint(Style::Cyan, format!("{}:", i18n::tr("hint", "hint")), on), h));
+        }
+
+        text
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self.kind() {
+            YiErrorKind::Cli(code)     => return *code,
+            YiErrorKind::Clap(e)       => return match e.kind {
+                clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => exit::OK,
+                _ => exit::USAGE,
+            },
+            YiErrorKind::ShellColor(_) => return exit::USAGE,
+            YiErrorKind::StdIo         => return exit::IO,
+            _                          => (),
+        }
+        if code::CONFIG.contains(&self.code()) {
+            return exit::CONFIG;
+        }
+
+        let mut err = self.source();
+        while let Some(next) = err {
+            if next.downcast_ref::<config::ConfigError>().is_some() {
+                return exit::CONFIG;
+            } else if next.downcast_ref::<std::io::Error>().is_some() {
+                return exit::IO;
+            }
+            err = next.source();
+        }
+
+        exit::FAILURE
+    }
+
+    fn _inner_print<T: StdError + 'static>(thing: Option<T>, level: Level) {
         thing.map_or((), |t| {
-            let cause = Self::_inner(&t);
+            let cause = Self::_chain(&t).join(", ");
 
             match level {
                 Level::Info  => log::info!("{}{}", t, cause),
@@ -104,20 +355,68 @@ impl YiError {
         })
     }
 
-    fn error_cause<T: Display + Fail>(thing: Option<T>) -> String {
-        thing.map_or(String::default(), |e| Self::_inner(&e))
+    fn error_cause<T: StdError + 'static>(thing: Option<T>) -> String {
+        thing.map_or(String::default(), |e| Self::_chain(&e).join(", "))
     }
 
-    fn _inner<T: Display + Fail>(e: &T) -> String {
-        let mut cause = String::default();
-        cause.push_str(&e.to_string());
-        let mut err: &dyn Fail = e;
-        while let Some(next) = err.cause() {
-            cause.push_str(&format!(", {}", next.to_string()));
-            err = next;
+    fn _chain(e: &(dyn StdError + 'static)) -> Vec<String> {
+        let own = |e: &(dyn StdError + 'static)| match e.downcast_ref::<YiError>() {
+            Some(yierr) => yierr.kind.to_string(),
+            None        => e.to_string(),
+        };
+
+        let mut chain = vec![own(e)];
+        let mut err = e.source();
+        while let Some(next) = err {
+            chain.push(own(next));
+            err = next.source();
         }
 
-        cause
+        chain.retain(|c| !c.is_empty());
+        chain
+    }
+}
+
+// Serializable form of a YiError for `--error-format json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct YiErrorReport {
+    pub kind: String,
+    pub code: String,
+    pub message: String,
+    pub causes: Vec<String>,
+    pub exit_code: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backtrace: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub context: BTreeMap<String, Value>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hints: Vec<String>,
+}
+
+impl YiErrorReport {
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+impl YiError {
+    pub fn report(&self) -> YiErrorReport {
+        let mut causes = self.chain();
+        let message = if causes.is_empty() { String::new() } else { causes.remove(0) };
+
+        YiErrorReport {
+            kind: self.kind.name().to_string(),
+            code: self.code().to_string(),
+            message,
+            causes,
+            exit_code: self.exit_code(),
+            backtrace: match self.detail.backtrace.status() {
+                BacktraceStatus::Captured => Some(self.detail.backtrace.to_string()),
+                _                         => None,
+            },
+            context: self.fields(),
+            hints: self.hints(),
+        }
     }
 }
 
@@ -127,34 +426,54 @@ pub trait YiResultExt<T, E> {
     fn to_yierr<C: Display + Send + Sync>(self, text: C) -> YiResult<T>;
 
     fn to_yicli(self) -> YiResult<T>;
+
+    fn to_yikind<K: Into<YiErrorKind>>(self, kind: K) -> YiResult<T>;
+
+    fn with_field<V: Serialize>(self, key: &str, value: V) -> YiResult<T>;
+
+    fn hint<H: Display>(self, hint: H) -> YiResult<T>;
 }
 
-impl<T, E: Fail> YiResultExt<T, E> for Result<T, E> {
+impl<T, E> YiResultExt<T, E> for Result<T, E>
+where E: StdError + Send + Sync + 'static
+{
     fn yierr(self) -> String {
         YiError::error_cause(self.err())
     }
 
     fn to_yierr<C: Display + Send + Sync>(self, err: C) -> YiResult<T> {
-        Ok(self.context(err.to_string())?)
+        self.map_err(|e| YiError::new(YiErrorKind::Info(err.to_string()), e))
     }
 
     fn to_yicli(self) -> YiResult<T> {
-        Ok(self.context(YiErrorKind::Cli(101))?)
+        self.map_err(|e| YiError::new(YiErrorKind::Cli(101), e))
+    }
+
+    fn to_yikind<K: Into<YiErrorKind>>(self, kind: K) -> YiResult<T> {
+        self.map_err(|e| YiError::new(kind.into(), e))
+    }
+
+    fn with_field<V: Serialize>(self, key: &str, value: V) -> YiResult<T> {
+        self.map_err(|e| YiError::wrap(e).with_field(key, value))
+    }
+
+    fn hint<H: Display>(self, hint: H) -> YiResult<T> {
+        self.map_err(|e| YiError::wrap(e).hint(hint))
     }
 }
 
 #[inline]
 pub fn to_yierr<T, E, C>(r: Result<T, E>, c: C) -> YiResult<T>
-where C: Display + Send + Sync, E: Fail
+where C: Display + Send + Sync, E: StdError + Send + Sync + 'static
 {
-    Ok(r.context(c.to_string())?)
+    r.to_yierr(c)
 }
 
 #[inline]
 pub fn with_yierr<T, E, C>(c: C) -> impl Fn(Result<T,E>) -> YiResult<T>
-where C: Display + Send + Sync, E: Fail,
+where C: Display + Send + Sync, E: StdError + Send + Sync + 'static,
 {
-    move |r: Result<T,E>| -> YiResult<T> { Ok(r.context(c.to_string())?) }
+    move |r: Result<T,E>| -> YiResult<T> { r.to_yierr(&c) }
 }
 
 #[inline]
@@ -174,24 +493,17 @@ where C: Display + Send + Sync, E: Display,
 impl From<YiErrorKind> for YiError {
     fn from(kind: YiErrorKind) -> YiError {
         YiError {
-            inner: Context::new(kind),
+            kind,
+            source: None,
+            detail: Box::new(Detail {
+                backtrace: Backtrace::capture(),
+                fields: BTreeMap::new(),
+                hints: Vec::new(),
+            }),
         }
     }
 }
 
-impl From<Context<YiErrorKind>> for YiError {
-    fn from(inner: Context<YiErrorKind>) -> YiError {
-        YiError { inner }
-    }
-}
-
-impl From<Context<String>> for YiError {
-    fn from(_e: Context<String>) -> YiError {
-        let inner = _e.map(YiErrorKind::Info);
-        YiError { inner }
-    }
-}
-
 impl From<YiError> for String {
     fn from(yierr: YiError) -> String {
         yierr.inner()
@@ -208,7 +520,7 @@ impl From<clap::Error> for YiError {
 
 impl From<std::io::Error> for YiError {
     fn from(_e: std::io::Error) -> YiError {
-        YiError { inner: _e.context(YiErrorKind::StdIo) }
+        YiError::new(YiErrorKind::StdIo, _e)
     }
 }
 
@@ -218,13 +530,6 @@ impl From<String> for YiError {
     }
 }
 
-impl From<Context<&'static str>> for YiError {
-    fn from(_e: Context<&'static str>) -> YiError {
-        let inner = _e.map(YiErrorKind::InfoStr);
-        YiError { inner }
-    }
-}
-
 impl From<&'static str> for YiError {
     fn from(_e: &'static str) -> YiError {
         YiError::from(YiErrorKind::InfoStr(_e))
@@ -248,8 +553,140 @@ where
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+
     // #[test]
     // fn yierr_from() -> crate::error::YiCli {
     //     Ok(())
     // }
+
+    #[test]
+    fn exit_code() {
+        let help = clap::App::new("t").get_matches_from_safe(vec!["t", "--help"]);
+        assert_eq!(YiError::from(help.unwrap_err()).exit_code(), exit::OK);
+
+        let usage = clap::App::new("t").get_matches_from_safe(vec!["t", "--nope"]);
+        assert_eq!(YiError::from(usage.unwrap_err()).exit_code(), exit::USAGE);
+
+        assert_eq!(YiError::from(YiErrorKind::Cli(3)).exit_code(), 3);
+        assert_eq!(YiError::from("boom").exit_code(), exit::FAILURE);
+        let rpc = YiError::from(crate::rpc::Error::Config).with_field("key", "rpc.bind");
+        assert_eq!(rpc.exit_code(), exit::CONFIG);
+
+        let io = std::fs::File::open("/nonexistent/yiapp").to_yierr("open");
+        assert_eq!(io.unwrap_err().exit_code(), exit::IO);
+
+        let conf = config::Config::default().merge(config::File::with_name("/nonexistent/yiapp"))
+            .map(|_| ()).to_yierr("loading from file");
+        assert_eq!(conf.unwrap_err().exit_code(), exit::CONFIG);
+    }
+
+    #[test]
+    fn std_error() {
+        let e = std::fs::File::open("/nonexistent/yiapp").to_yierr("open log").unwrap_err();
+        let io = e.source().and_then(|s| s.downcast_ref::<std::io::Error>()).unwrap();
+        assert_eq!(io.kind(), std::io::ErrorKind::NotFound);
+        assert!(e.inner().starts_with("open log, "));
+        assert_eq!(e.to_string(), "[YI-GEN-001] open log");
+        assert!(format!("{:#}", e).starts_with("[YI-GEN-001] open log, "));
+
+        // no repeated chain when a YiError wraps another one
+        let outer = Err::<(), _>(e).to_yierr("start").unwrap_err();
+        assert_eq!(outer.chain().len(), 3);
+        assert_eq!(outer.chain()[1], "open log");
+
+        let boxed: Result<(), Box<dyn StdError + Send + Sync>> = (|| {
+            Err(YiError::from("boom"))?;
+            Ok(())
+        })();
+        assert_eq!(boxed.unwrap_err().to_string(), "[YI-GEN-001] boom");
+
+        let with = with_yierr("parse");
+        assert_eq!(with("x".parse::<u32>()).unwrap_err().chain().len(), 2);
+    }
+
+    #[test]
+    fn codes() {
+        let e = std::fs::File::open("/nonexistent/yiapp").to_yikind(crate::arg::Error::File)
+            .unwrap_err();
+        assert_eq!(e.code(), "YI-CFG-001");
+        assert_eq!(e.to_string(), "[YI-CFG-001] loading from file");
+
+        let outer = Err::<(), _>(e).to_yierr("start").unwrap_err();
+        assert_eq!(outer.code(), "YI-CFG-001");
+        assert_eq!(YiError::from("boom").code(), code::GENERIC);
+
+        for kind in &[YiErrorKind::Info(String::new()), YiErrorKind::ShellColor(String::new()),
+                      YiErrorKind::Cli(0), YiErrorKind::Opt(String::new()), YiErrorKind::StdIo,
+                      YiErrorKind::Ver { found: String::new(), required: String::new() },
+                      YiErrorKind::Ack { ty: "", value: String::new() },
+                      YiErrorKind::Unknown] {
+            assert!(code::lookup(kind.code()).is_some(), "{}", kind.code());
+        }
+    }
+
+    #[test]
+    fn coded_text() {
+        use crate::i18n::Locale;
+        assert_eq!(coded(Locale::ZhCn, "YI-RPC-005", "rpc configuration"), "rpc 配置");
+        assert_eq!(coded(Locale::ZhCn, "YI-CFG-001", &crate::arg::Error::File.to_string()),
+                   "加载配置文件");
+        assert_eq!(coded(Locale::ZhCn, "YI-RPC-005", "bad rpc.bind: x"), "rpc 配置: bad rpc.bind: x");
+        assert_eq!(coded(Locale::En, "YI-RPC-005", "bad rpc.bind: x"), "bad rpc.bind: x");
+    }
+
+    #[test]
+    fn report() {
+        let e = std::fs::File::open("/nonexistent/yiapp").to_yikind(crate::arg::Error::File);
+        let e = e.to_yierr("start").unwrap_err();
+
+        let v: serde_json::Value = serde_json::from_str(&e.report().to_json()).unwrap();
+        assert_eq!(v["kind"], "Info");
+        assert_eq!(v["code"], "YI-CFG-001");
+        assert_eq!(v["message"], "start");
+        assert_eq!(v["causes"][0], "loading from file");
+        assert_eq!(v["causes"].as_array().unwrap().len(), 2);
+        assert_eq!(v["exit_code"], exit::CONFIG);
+
+        let ver = YiError::from(YiErrorKind::Ver { found: "2".into(), required: "1".into() });
+        assert_eq!(ver.report().kind, "Ver");
+        assert_eq!(YiError::from(YiErrorKind::from(crate::arg::Error::File)).report().kind, "Code");
+    }
+
+    #[test]
+    fn context() {
+        let e = std::fs::File::open("/nonexistent/yiapp")
+            .to_yikind(crate::arg::Error::LogFile)
+            .with_field("path", "/nonexistent/yiapp")
+            .hint("check permissions on log/");
+        let e = e.to_yierr("start").with_field("attempt", 2).unwrap_err();
+
+        assert_eq!(e.code(), "YI-IO-002");
+        assert_eq!(e.fields()["path"], "/nonexistent/yiapp");
+        assert_eq!(e.fields()["attempt"], 2);
+        assert_eq!(e.hints(), vec!["check permissions on log/"]);
+        assert!(e.render().contains("\n  path: /nonexistent/yiapp"));
+        assert!(e.render().ends_with(" check permissions on log/"));
+
+        let v: serde_json::Value = serde_json::from_str(&e.report().to_json()).unwrap();
+        assert_eq!(v["context"]["attempt"], 2);
+        assert_eq!(v["hints"][0], "check permissions on log/");
+        assert_eq!(v["message"], "start");
+
+        // a plain error gets wrapped, keeping its text
+        let e = "x".parse::<u32>().hint("use a number").unwrap_err();
+        assert_eq!(e.report().message, "invalid digit found in string");
+        assert_eq!(e.to_string(), "[YI-GEN-001] invalid digit found in string");
+        assert_eq!(format!("{:#}", e), e.to_string());
+        assert_eq!(e.chain().len(), 1);
+        assert!(YiError::from("boom").report().hints.is_empty());
+    }
+
+    #[test]
+    fn render_skips_empty() {
+        let e = std::fs::File::open("/nonexistent/yiapp").to_yicli().unwrap_err();
+        assert_eq!(e.exit_code(), 101);
+        assert!(!e.render().contains(", ,"));
+        assert!(!e.render().contains(": ,"));
+    }
 }
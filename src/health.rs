@@ -0,0 +1,304 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use serde::{Deserialize, Serialize};
+
+use super::error::{YiError, YiErrorKind, YiResult, YiResultExt};
+use super::{logger, shutdown};
+#[cfg(all(unix, feature = "systemd"))]
+use super::systemd;
+
+// a request head larger than this is cut off
+const MAX_HEAD: usize = 8 << 10;
+// for reading the request and writing the response
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    Bind,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Bind => write!(f, "health endpoint"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Bind => "YI-HLT-001",
+        }
+    }
+}
+
+impl From<Error> for YiErrorKind {
+    fn from(e: Error) -> YiErrorKind {
+        YiErrorKind::Code(e.code(), e.to_string())
+    }
+}
+
+impl From<Error> for YiError {
+    fn from(e: Error) -> YiError {
+        YiError::from(YiErrorKind::from(e))
+    }
+}
+
+// ordered from good to bad, the report takes the worst of its checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Degraded,
+    Down,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Up       => write!(f, "up"),
+            Status::Degraded => write!(f, "degraded"),
+            Status::Down     => write!(f, "down"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Check {
+    pub status: Status,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub updated: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Report {
+    pub status: Status,
+    pub ready: bool,
+    pub checks: BTreeMap<String, Check>,
+}
+
+impl Report {
+    pub fn healthy(&self) -> bool {
+        self.status != Status::Down
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "status: {}", self.status)?;
+        writeln!(f, "ready: {}", self.ready)?;
+        for (name, c) in &self.checks {
+            match &c.message {
+                Some(m) => writeln!(f, "  {}: {} ({})", name, c.status, m)?,
+                None    => writeln!(f, "  {}: {}", name, c.status)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+type Probe = Box<dyn Fn() -> Vec<(String, YiResult<()>)> + Send + Sync>;
+
+// Health of the app: checks reported by its components, probes asked on
+// every report, and readiness, set by `App::start` and cleared on shutdown.
+#[derive(Default)]
+pub struct Health {
+    checks: RwLock<BTreeMap<String, Check>>,
+    probes: RwLock<Vec<Probe>>,
+    ready: AtomicBool,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn report(&self, name: &str, status: Status, message: Option<&str>) {
+        let check = Check {
+            status,
+            message: message.map(String::from),
+            updated: logger::timestamp(SystemTime::now()),
+        };
+        self.checks.write().unwrap_or_else(|e| e.into_inner()).insert(name.to_string(), check);
+    }
+
+    pub fn up(&self, name: &str) {
+        self.report(name, Status::Up, None)
+    }
+
+    pub fn degraded(&self, name: &str, message: &str) {
+        self.report(name, Status::Degraded, Some(message))
+    }
+
+    pub fn down(&self, name: &str, message: &str) {
+        self.report(name, Status::Down, Some(message))
+    }
+
+    // checks asked for on every report, an error is `Down`
+    pub fn probe<F>(&self, probe: F)
+    where F: Fn() -> Vec<(String, YiResult<()>)> + Send + Sync + 'static
+    {
+        self.probes.write().unwrap_or_else(|e| e.into_inner()).push(Box::new(probe));
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Report {
+        let mut checks = self.checks.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let now = logger::timestamp(SystemTime::now());
+        for probe in self.probes.read().unwrap_or_else(|e| e.into_inner()).iter() {
+            for (name, r) in probe() {
+                let (status, message) = match r {
+                    Ok(())  => (Status::Up, None),
+                    Err(e)  => (Status::Down, Some(format!("{:#}", e))),
+                };
+                checks.insert(name, Check { status, message, updated: now.clone() });
+            }
+        }
+
+        Report {
+            status: checks.values().map(|c| c.status).max().unwrap_or(Status::Up),
+            ready: self.ready.load(Ordering::Relaxed) && !shutdown::internal_token().is_triggered(),
+            checks,
+        }
+    }
+
+    // Serve `/healthz` and `/readyz` on `bind`, 200 or 503 with the report
+    // as JSON. Returns the bound address.
+    pub fn serve(self: &Arc<Self>, bind: &str) -> YiResult<String> {
+        let listener = match bind.strip_prefix("systemd:") {
+            #[cfg(all(unix, feature = "systemd"))]
+            Some(name) => match systemd::take(name).to_yikind(Error::Bind)? {
+                systemd::Socket::Tcp(l) => l,
+                systemd::Socket::Unix(_) => return Err(YiError::from(Error::Bind)
+                                                       .with_field("bind", bind)
+                                                       .hint("the health endpoint needs a TCP socket")),
+            },
+            _ => TcpListener::bind(bind).to_yikind(Error::Bind).with_field("bind", bind)?,
+        };
+        let addr = listener.local_addr().to_yikind(Error::Bind)?.to_string();
+        log::info!("health endpoint on http://{}/healthz", addr);
+
+        let health = self.clone();
+        thread::Builder::new().name("health".to_string()).spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // a slow client doesn't hold up the next probe
+                let health = health.clone();
+                let _ = thread::Builder::new().name("health-conn".to_string()).spawn(move || {
+                    if let Err(e) = health.respond(stream) {
+                        log::debug!("health endpoint: {:#}", e);
+                    }
+                });
+            }
+        }).to_yikind(Error::Bind)?;
+
+        Ok(addr)
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> YiResult<()> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        let mut head = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < MAX_HEAD {
+            let n = stream.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            head.extend_from_slice(&buf[..n]);
+        }
+
+        let head = String::from_utf8_lossy(&head);
+        let mut line = head.lines().next().unwrap_or_default().split_whitespace();
+        let (method, path) = (line.next().unwrap_or_default(), line.next().unwrap_or_default());
+        let path = path.split('?').next().unwrap_or_default();
+
+        let report = self.snapshot();
+        let (code, body) = match (method, path) {
+            ("GET", "/healthz") | ("HEAD", "/healthz") => (report.healthy(), report),
+            ("GET", "/readyz") | ("HEAD", "/readyz")   => (report.healthy() && report.ready, report),
+            ("GET", _) | ("HEAD", _) => return write(&mut stream, "404 Not Found", ""),
+            _                        => return write(&mut stream, "405 Method Not Allowed", ""),
+        };
+
+        let body = serde_json::to_string(&body).to_yierr("health report")?;
+        let status = if code { "200 OK" } else { "503 Service Unavailable" };
+        write(&mut stream, status, if method == "HEAD" { "" } else { &body })
+    }
+}
+
+fn write(stream: &mut TcpStream, status: &str, body: &str) -> YiResult<()> {
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                    Connection: close\r\n\r\n{}", status, body.len(), body)?;
+    stream.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(addr: &str, path: &str) -> (String, String) {
+        let mut s = TcpStream::connect(addr).unwrap();
+        write!(s, "GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path).unwrap();
+        let mut text = String::new();
+        s.read_to_string(&mut text).unwrap();
+        let (head, body) = text.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+
+    #[test]
+    fn report() {
+        let health = Health::new();
+        assert_eq!(health.snapshot().status, Status::Up);
+
+        health.up("db");
+        health.degraded("cache", "slow");
+        let r = health.snapshot();
+        assert_eq!(r.status, Status::Degraded);
+        assert!(r.healthy() && !r.ready);
+        assert_eq!(r.checks["cache"].message.as_deref(), Some("slow"));
+
+        health.probe(|| vec![("queue".to_string(), Err(YiError::from("full")))]);
+        let r = health.snapshot();
+        assert_eq!(r.status, Status::Down);
+        assert!(r.to_string().contains("  queue: down ([YI-GEN-001] full)"));
+    }
+
+    #[test]
+    fn endpoint() {
+        let health = Arc::new(Health::new());
+        health.up("db");
+        let addr = health.serve("127.0.0.1:0").unwrap();
+
+        let (status, body) = get(&addr, "/healthz");
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        let r: Report = serde_json::from_str(&body).unwrap();
+        assert_eq!(r.checks["db"].status, Status::Up);
+
+        assert_eq!(get(&addr, "/readyz").0, "HTTP/1.1 503 Service Unavailable");
+        health.set_ready(true);
+        assert_eq!(get(&addr, "/readyz?verbose").0, "HTTP/1.1 200 OK");
+        assert_eq!(get(&addr, "/nope").0, "HTTP/1.1 404 Not Found");
+
+        // an idle connection doesn't block the others
+        let _idle = TcpStream::connect(&addr).unwrap();
+        let start = std::time::Instant::now();
+        health.down("db", "gone");
+        assert_eq!(get(&addr, "/healthz").0, "HTTP/1.1 503 Service Unavailable");
+        assert!(start.elapsed() < TIMEOUT);
+    }
+}
@@ -0,0 +1,181 @@
+use std::env;
+use std::fmt::Display;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// 0 until set or first read from the environment
+static LOCALE: AtomicU8 = AtomicU8::new(0);
+
+static CATALOG: RwLock<BTreeMap<(Locale, &'static str), &'static str>> = RwLock::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Locale {
+    En = 1,
+    ZhCn = 2,
+}
+
+impl Locale {
+    // POSIX locale names as in LANG, e.g. zh_CN.UTF-8, or zh-CN; anything
+    // we have no messages for falls back to English
+    pub fn from_name(name: &str) -> Self {
+        let lang = name.split(['.', '@']).next().unwrap_or_default().to_lowercase();
+        if lang == "zh" || lang.starts_with("zh_") || lang.starts_with("zh-") {
+            Locale::ZhCn
+        } else {
+            Locale::En
+        }
+    }
+
+    // LC_ALL, LC_MESSAGES, then LANG, the first one set wins
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"].iter()
+            .filter_map(|k| env::var(k).ok())
+            .find(|v| !v.is_empty())
+            .map(|v| Self::from_name(&v))
+            .unwrap_or(Locale::En)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Locale::En   => "en",
+            Locale::ZhCn => "zh-CN",
+        }
+    }
+}
+
+pub fn set(locale: Locale) {
+    LOCALE.store(locale as u8, Ordering::Relaxed);
+}
+
+pub fn get() -> Locale {
+    match LOCALE.load(Ordering::Relaxed) {
+        1 => Locale::En,
+        2 => Locale::ZhCn,
+        _ => {
+            let locale = Locale::from_env();
+            set(locale);
+            locale
+        }
+    }
+}
+
+// Messages of an application for `locale`, keyed by error code, e.g.
+// "SHOP-DB-001", or by help key, "help.<arg>" and "about.<app>".
+pub fn register(locale: Locale, messages: &'static [(&'static str, &'static str)]) {
+    let mut catalog = CATALOG.write().unwrap_or_else(|e| e.into_inner());
+    catalog.extend(messages.iter().map(|(k, v)| ((locale, *k), *v)));
+}
+
+// `key` in the selected locale, `default` is the English text
+pub fn tr<'a>(key: &str, default: &'a str) -> &'a str {
+    lookup(get(), key).unwrap_or(default)
+}
+
+// `tr` with every `{}` replaced by the next of `args`
+pub fn trf(key: &str, default: &str, args: &[&dyn Display]) -> String {
+    let mut args = args.iter();
+    let mut parts = tr(key, default).split("{}");
+    let mut text = parts.next().unwrap_or_default().to_string();
+    for part in parts {
+        if let Some(arg) = args.next() {
+            text.push_str(&arg.to_string());
+        }
+        text.push_str(part);
+    }
+    text
+}
+
+pub(crate) fn lookup(locale: Locale, key: &str) -> Option<&'static str> {
+    let catalog = CATALOG.read().unwrap_or_else(|e| e.into_inner());
+    catalog.get(&(locale, key)).copied().or_else(|| match locale {
+        Locale::ZhCn => ZH_CN.iter().find(|(k, _)| *k == key).map(|(_, v)| *v),
+        Locale::En   => None,
+    })
+}
+
+const ZH_CN: &[(&str, &str)] = &[
+    ("YI-GEN-000", "发生了未知错误。"),
+    ("YI-CLI-001", "--color 参数必须是 auto、always 或 never，实际为：{}"),
+    ("YI-VER-001", "版本 {} 不满足 {}"),
+    ("YI-ACK-001", "未知的 {} 代码 {}"),
+    ("YI-IO-001", "读写错误"),
+    ("YI-IO-002", "打开日志文件"),
+    ("YI-IO-003", "打开错误文件"),
+    ("YI-RPC-001", "rpc 监听"),
+    ("YI-RPC-002", "rpc 连接"),
+    ("YI-RPC-003", "rpc 帧"),
+    ("YI-RPC-004", "rpc 远端错误"),
+    ("YI-RPC-005", "rpc 配置"),
+    ("YI-ADM-001", "管理套接字"),
+    ("YI-ADM-002", "连接管理套接字"),
+    ("YI-ADM-003", "未知的管理命令"),
+    ("YI-CMP-001", "启动组件"),
+    ("YI-CMP-002", "停止组件"),
+    ("YI-CMP-003", "组件依赖"),
+    ("YI-CMP-004", "组件配置段"),
+    ("YI-HLT-001", "健康检查端点"),
+    ("YI-SYS-001", "通知 systemd"),
+    ("YI-SYS-002", "来自 systemd 的套接字"),
+    ("YI-DMN-001", "启动握手"),
+    ("YI-DMN-002", "子进程未及时就绪"),
+    ("YI-DMN-003", "子进程在启动期间退出"),
+    ("YI-DMN-004", "守护进程配置"),
+    ("YI-DMN-005", "未知的用户或组"),
+    ("YI-DMN-006", "切换根目录"),
+    ("YI-DMN-007", "设置资源限制"),
+    ("YI-DMN-008", "降低权限"),
+    ("YI-CFG-001", "加载配置文件"),
+    ("YI-CFG-002", "从环境变量加载配置"),
+    ("YI-CFG-003", "匹配命令行参数失败"),
+    ("YI-CFG-004", "日志配置"),
+    ("error", "错误"),
+    ("hint", "提示"),
+    ("hint.file", "扩展名可以省略，会依次尝试 toml、json、yaml、hjson 和 ini"),
+    ("hint.env", "请检查 {}_* 环境变量"),
+    ("hint.log", "请检查 log/ 目录是否存在且可写"),
+    ("help.help", "打印帮助信息"),
+    ("help.version", "打印版本信息"),
+    ("help.color", "着色：auto、always、never"),
+    ("help.error-format", "错误输出到 stderr 的格式"),
+    ("about.version", "打印版本信息"),
+    ("help.version.verbose", "打印构建和提交详情"),
+    ("help.status.json", "以 JSON 格式打印报告"),
+    ("help.version.json", "以 JSON 格式打印版本信息"),
+    ("about.ctl", "向运行中的进程发送管理命令"),
+    ("about.unit", "打印应用的 systemd 服务单元"),
+    ("help.unit.watchdog", "设置单元的 WatchdogSec="),
+    ("help.unit.args", "ExecStart= 的参数"),
+    ("about.status", "打印运行中进程的健康和就绪状态"),
+    ("about.explain", "解释错误码，例如 YI-CFG-001"),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(Locale::from_name("zh_CN.UTF-8"), Locale::ZhCn);
+        assert_eq!(Locale::from_name("zh-CN"), Locale::ZhCn);
+        assert_eq!(Locale::from_name("zh"), Locale::ZhCn);
+        assert_eq!(Locale::from_name("en_US.UTF-8"), Locale::En);
+        assert_eq!(Locale::from_name("C"), Locale::En);
+        assert_eq!(Locale::from_name("zu_ZA"), Locale::En);
+    }
+
+    #[test]
+    fn catalog() {
+        register(Locale::ZhCn, &[("SHOP-DB-001", "数据库不可用")]);
+        assert_eq!(lookup(Locale::ZhCn, "SHOP-DB-001"), Some("数据库不可用"));
+        assert_eq!(lookup(Locale::En, "SHOP-DB-001"), None);
+        assert_eq!(lookup(Locale::ZhCn, "YI-CFG-001"), Some("加载配置文件"));
+        assert_eq!(lookup(Locale::ZhCn, "help.nope"), None);
+        assert_eq!(trf("help.nope", "{} of {}", &[&1, &"2"]), "1 of 2");
+
+        let keys: Vec<_> = ZH_CN.iter().map(|(k, _)| k).collect();
+        for (i, k) in keys.iter().enumerate() {
+            assert!(!keys[i + 1..].contains(k), "{}", k);
+        }
+    }
+}
@@ -5,47 +5,377 @@
 // #![allow(unused_mut)]
 
 pub mod error;
+pub mod code;
 pub mod arg;
+pub mod logger;
+pub mod color;
+pub mod version;
+pub mod crash;
+pub mod build;
+pub mod migrate;
+pub mod i18n;
+pub mod rpc;
+pub mod shutdown;
+pub mod component;
+pub mod health;
+#[cfg(unix)]
+pub mod admin;
+#[cfg(unix)]
+pub mod daemon;
+#[cfg(all(unix, feature = "systemd"))]
+pub mod systemd;
 
 pub use clap;
+pub use serde;
+
+// used by the old `yiack!(Status, u16, TABLE)` form for its warning
+#[doc(hidden)]
+#[deprecated(note = "pass the yiack! table inline, e.g. yiack!(Status, u16, STATUS = [(Status::Ok, 0)])")]
+pub const YIACK_TABLE_EXPR: () = ();
 
 mod macros {
+    // Two way mapping between an enum and the codes of a table, declared by
+    // the macro as `const STATUS: &[(Status, u16)]`, e.g. protocol status codes:
+    //
+    //     yiack!(Status, u16, STATUS = [(Status::Ok, 0), (Status::Busy, 1), (Status::Unknown, 0xffff)]);
+    //
+    // `Status::try_from(..)` fails with `YiErrorKind::Ack` for a code missing
+    // from the table. With a fourth argument, `yiack!(.., Status::Unknown)`,
+    // `AsRef<Status> for u16` maps such a code to that variant. A variant
+    // missing from the table or a code listed twice doesn't compile.
+    // `pub STATUS = [..]` exports the table.
+    //
+    // The old form with a separate const, `yiack!(Status, u16, STATUS)`, still
+    // works with a deprecation warning. It converts codes to `&'static Status`
+    // and panics for a variant missing from the table.
+    //
+    // The enum needs no derives. Declared in the macro instead it also
+    // serializes as its code and `iter()` lists all variants, e.g. for help
+    // text:
+    //
+    //     yiack! {
+    //         pub enum Status: u16 {
+    //             Ok = 0,
+    //             Busy = 1,
+    //             Unknown = 0xffff,
+    //         } else Unknown
+    //     }
+    //
+    // The enum derives Debug, Clone, Copy, PartialEq, Eq and Hash. `else`
+    // is optional, it maps unknown codes for `AsRef` and deserialization.
     #[macro_export] macro_rules! yiack {
-        ($enum:ty, $num:ty, $arrays:expr) => {
+        ($(#[$meta:meta])* $vis:vis enum $name:ident : $num:ty {
+            $($(#[$vmeta:meta])* $var:ident = $code:literal),+ $(,)?
+        } $(else $unknown:ident)?) => {
+            $(#[$meta])*
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            $vis enum $name {
+                $($(#[$vmeta])* $var),+
+            }
+
+            $crate::yiack!(@check_dups $num, &[$(((), $code)),+]);
+
+            // a private table may not use all of these
+            #[allow(dead_code)]
+            impl $name {
+                pub const ALL: &'static [$name] = &[$($name::$var),+];
+
+                pub fn code(self) -> $num {
+                    match self {
+                        $($name::$var => $code),+
+                    }
+                }
+
+                pub fn as_str(self) -> &'static str {
+                    match self {
+                        $($name::$var => stringify!($var)),+
+                    }
+                }
+
+                pub fn iter() -> impl Iterator<Item = $name> {
+                    Self::ALL.iter().copied()
+                }
+
+                fn to_static(self) -> &'static $name {
+                    match self {
+                        $($name::$var => &$name::$var),+
+                    }
+                }
+
+                // `try_from`, or the `else` variant for an unknown code
+                pub fn from_code(code: $num) -> Result<Self, $crate::error::YiErrorKind> {
+                    let r = <Self as std::convert::TryFrom<$num>>::try_from(code);
+                    $(let r = r.or(Ok($name::$unknown));)?
+                    r
+                }
+            }
+
+            impl std::ops::Deref for $name {
+                type Target = $num;
+                fn deref(&self) -> &$num {
+                    match self {
+                        $($name::$var => &$code),+
+                    }
+                }
+            }
+
+            impl std::convert::AsRef<$num> for $name {
+                fn as_ref(&self) -> &$num {
+                    self
+                }
+            }
+
+            $(impl std::convert::AsRef<$name> for $num {
+                fn as_ref(&self) -> &$name {
+                    $name::from_code(*self).map($name::to_static).unwrap_or(&$name::$unknown)
+                }
+            })?
+
+            impl std::convert::TryFrom<$num> for $name {
+                type Error = $crate::error::YiErrorKind;
+
+                fn try_from(code: $num) -> Result<Self, Self::Error> {
+                    match code {
+                        $($code => Ok($name::$var),)+
+                        _ => Err($crate::error::YiErrorKind::Ack {
+                            ty: stringify!($name),
+                            value: code.to_string(),
+                        }),
+                    }
+                }
+            }
+
+            impl std::fmt::Display for $name {
+                fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str(self.as_str())
+                }
+            }
+
+            impl $crate::serde::Serialize for $name {
+                fn serialize<S: $crate::serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+                    $crate::serde::Serialize::serialize(&self.code(), s)
+                }
+            }
+
+            impl<'de> $crate::serde::Deserialize<'de> for $name {
+                fn deserialize<D: $crate::serde::Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+                    let code = <$num as $crate::serde::Deserialize>::deserialize(d)?;
+                    $name::from_code(code).map_err($crate::serde::de::Error::custom)
+                }
+            }
+        };
+
+        ($enum:ty, $num:ty, $vis:vis $table:ident = [$(($var:path, $code:expr)),+ $(,)?]) => {
+            $crate::yiack!(@table $enum, $num, $vis $table, [$(($var, $code)),+]);
+        };
+
+        ($enum:ty, $num:ty, $vis:vis $table:ident = [$(($var:path, $code:expr)),+ $(,)?],
+         $unknown:path) => {
+            $crate::yiack!(@table $enum, $num, $vis $table, [$(($var, $code)),+]);
+
+            impl std::convert::AsRef<$enum> for $num {
+                fn as_ref(&self) -> &$enum {
+                    $(if *self == $code {
+                        return &$var;
+                    })+
+                    &$unknown
+                }
+            }
+        };
+
+        // The table as a separate const, variants missing from it are only
+        // found at runtime. Deprecated, use the inline table.
+        ($enum:ty, $num:ty, $table:expr $(, $unknown:expr)?) => {
+            const _: () = $crate::YIACK_TABLE_EXPR;
+            $crate::yiack!(@check_dups $num, $table);
 
             impl std::ops::Deref for $enum {
                 type Target = $num;
                 fn deref(&self) -> &$num {
-                    $arrays.iter().find(|&(t, _)| self == t)
-                        .map(|(_, c)| c)
-                        .unwrap_or(&$arrays[$arrays.len()-1].1)
+                    $table.iter().find(|(t, _)| self == t).map(|(_, c)| c)
+                        .expect(concat!("variant missing from the yiack! table of ", stringify!($enum)))
                 }
             }
 
             impl std::convert::AsRef<$num> for $enum {
                 fn as_ref(&self) -> &$num {
-                    $arrays.iter().find(|&(t, _)| self == t)
-                        .map(|(_, c)| c)
-                        .unwrap_or(&$arrays[$arrays.len()-1].1)
+                    self
                 }
             }
 
-            impl std::convert::AsRef<$enum> for $num {
+            $(impl std::convert::AsRef<$enum> for $num {
                 fn as_ref(&self) -> &$enum {
-                    $arrays.iter().find(|&(_, c)| *self == *c)
-                        .map(|(t, _)| t)
-                        .unwrap_or(&$arrays[$arrays.len()-1].0)
+                    $table.iter().find(|(_, c)| self == c).map(|(t, _)| t).unwrap_or(&$unknown)
+                }
+            })?
+
+            impl std::convert::TryFrom<$num> for &'static $enum {
+                type Error = $crate::error::YiErrorKind;
+
+                fn try_from(code: $num) -> Result<Self, Self::Error> {
+                    $table.iter().find(|(_, c)| code == *c).map(|(t, _)| t)
+                        .ok_or_else(|| $crate::error::YiErrorKind::Ack {
+                            ty: stringify!($enum),
+                            value: code.to_string(),
+                        })
                 }
             }
+        };
 
-        }
+        // a code listed twice doesn't compile
+        (@check_dups $num:ty, $table:expr) => {
+            const _: () = {
+                let table: &[(_, $num)] = $table;
+                let mut i = 0;
+                while i < table.len() {
+                    let mut j = i + 1;
+                    while j < table.len() {
+                        assert!(table[i].1 != table[j].1, "duplicate code in yiack! table");
+                        j += 1;
+                    }
+                    i += 1;
+                }
+            };
+        };
+
+        (@table $enum:ty, $num:ty, $vis:vis $table:ident, [$(($var:path, $code:expr)),+]) => {
+            #[allow(dead_code)]
+            $vis const $table: &[($enum, $num)] = &[$(($var, $code)),+];
+            $crate::yiack!(@check_dups $num, $table);
+
+            impl std::ops::Deref for $enum {
+                type Target = $num;
+                fn deref(&self) -> &$num {
+                    match self {
+                        $($var => {
+                            const CODE: $num = $code;
+                            &CODE
+                        })+
+                    }
+                }
+            }
+
+            impl std::convert::AsRef<$num> for $enum {
+                fn as_ref(&self) -> &$num {
+                    self
+                }
+            }
+
+            impl std::convert::TryFrom<$num> for $enum {
+                type Error = $crate::error::YiErrorKind;
+
+                fn try_from(code: $num) -> Result<Self, Self::Error> {
+                    $(if code == $code {
+                        return Ok($var);
+                    })+
+                    Err($crate::error::YiErrorKind::Ack {
+                        ty: stringify!($enum),
+                        value: code.to_string(),
+                    })
+                }
+            }
+        };
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::str;
+    use std::convert::TryFrom;
+    use crate::error::YiErrorKind;
+    use crate::yiack;
+
+    #[derive(Debug, PartialEq)]
+    enum Status { Ok, Busy, Unknown }
+
+    yiack!(Status, u16, STATUS = [(Status::Ok, 0), (Status::Busy, 1), (Status::Unknown, 0xffff)],
+           Status::Unknown);
+
+    #[derive(Debug, PartialEq)]
+    enum Strict { A, B }
+
+    yiack!(Strict, u8, STRICT = [(Strict::A, 1), (Strict::B, 2)]);
+
+    yiack! {
+        // reply status of the test protocol
+        pub enum Reply: u16 {
+            Ok = 0,
+            Busy = 1,
+            Unknown = 0xffff,
+        } else Unknown
+    }
+
+    yiack! {
+        enum Mode: u8 { Read = 1, Write = 2 }
+    }
+
+    #[test]
+    fn ack_enum() {
+        assert_eq!(Reply::Busy.code(), 1);
+        assert_eq!(*Reply::Unknown, 0xffff);
+        assert_eq!(Reply::try_from(1).unwrap(), Reply::Busy);
+        assert_eq!(Reply::from_code(7).unwrap(), Reply::Unknown);
+        assert_eq!(AsRef::<Reply>::as_ref(&7u16), &Reply::Unknown);
+        assert!(matches!(Reply::try_from(7), Err(YiErrorKind::Ack { ty: "Reply", .. })));
+        assert_eq!(Reply::iter().map(|r| r.to_string()).collect::<Vec<_>>(),
+                   ["Ok", "Busy", "Unknown"]);
+
+        assert_eq!(serde_json::to_string(&[Reply::Ok, Reply::Busy]).unwrap(), "[0,1]");
+        assert_eq!(serde_json::from_str::<Reply>("9").unwrap(), Reply::Unknown);
+
+        assert_eq!(Mode::ALL, &[Mode::Read, Mode::Write]);
+        assert_eq!(serde_json::from_str::<Mode>("2").unwrap(), Mode::Write);
+        let e = serde_json::from_str::<Mode>("3").unwrap_err().to_string();
+        assert!(e.contains("Mode") && e.contains('3'), "{}", e);
+
+        let mut c = config::Config::default();
+        c.set("mode", 1).unwrap();
+        assert_eq!(c.get::<Mode>("mode").unwrap(), Mode::Read);
+    }
+
+    #[test]
+    fn ack() {
+        assert_eq!(*Status::Busy, 1);
+        assert_eq!(AsRef::<u16>::as_ref(&Status::Unknown), &0xffff);
+        assert_eq!(Status::try_from(1).unwrap(), Status::Busy);
+        assert_eq!(AsRef::<Status>::as_ref(&7u16), &Status::Unknown);
+
+        assert_eq!(*Strict::B, 2);
+        assert_eq!(Strict::try_from(1u8).unwrap(), Strict::A);
+        assert_eq!(STRICT.len(), 2);
+        // no `else` variant, unknown codes are an error
+        match Strict::try_from(9u8) {
+            Err(YiErrorKind::Ack { ty, value }) => {
+                assert_eq!(ty, "Strict");
+                assert_eq!(value, "9");
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[allow(deprecated)]
+    mod old {
+        use crate::yiack;
+
+        #[derive(Debug, PartialEq)]
+        pub enum Old { A, B, Other }
+
+        pub const OLD: &[(Old, u8)] = &[(Old::A, 1), (Old::B, 2), (Old::Other, 0)];
+
+        yiack!(Old, u8, OLD, Old::Other);
+    }
+
+    #[test]
+    fn ack_table_expr() {
+        use self::old::Old;
+        assert_eq!(*Old::B, 2);
+        assert_eq!(<&Old>::try_from(1u8).unwrap(), &Old::A);
+        assert!(matches!(<&Old>::try_from(9u8), Err(YiErrorKind::Ack { ty: "Old", .. })));
+        assert_eq!(AsRef::<Old>::as_ref(&9u8), &Old::Other);
+    }
+
     #[test]
+    #[allow(clippy::unit_arg, clippy::assertions_on_constants, unused_must_use, non_fmt_panics)]
     fn scratch() {
         (|| Ok(assert!(true, "on".parse::<bool>()?)) )()
             .err().map_or((), |e: str::ParseBoolError| println!("{}", e));
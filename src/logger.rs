@@ -0,0 +1,290 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::{OnceLock, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use log::{LevelFilter, Log, Metadata, Record};
+use log::kv::{self, VisitSource};
+
+use super::error::{YiError, YiResult};
+use super::color::{self, Stream, Style};
+use super::build;
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = YiError;
+
+    fn from_str(s: &str) -> YiResult<Self> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _      => Err(YiError::from(format!("unknown log format: {}", s))),
+        }
+    }
+}
+
+// `log` section of the app config, e.g.
+//   [log]
+//   level = "debug"
+//   format = "json"
+//   fields = { dc = "sh-1" }
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub level: String,
+    pub format: LogFormat,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+            fields: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    name: String,
+    version: String,
+    format: LogFormat,
+    fields: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+pub struct Logger {
+    pid: u32,
+    // false when another logger won `log::set_logger`, its level is left alone
+    installed: AtomicBool,
+    inner: RwLock<Inner>,
+}
+
+impl Logger {
+    pub fn new(name: &str, version: &str) -> Self {
+        Logger {
+            pid: std::process::id(),
+            installed: AtomicBool::new(false),
+            inner: RwLock::new(Inner {
+                name: name.to_string(),
+                version: version.to_string(),
+                format: LogFormat::Text,
+                fields: BTreeMap::new(),
+            }),
+        }
+    }
+
+    pub fn configure(&self, config: &LogConfig) -> YiResult<()> {
+        let level = LevelFilter::from_str(&config.level)
+            .map_err(|_| YiError::from(format!("unknown log level: {}", config.level)))?;
+        self.set_level(level);
+
+        let mut inner = self.inner.write().unwrap_or_else(|e| e.into_inner());
+        inner.format = config.format;
+        inner.fields.extend(config.fields.clone());
+        Ok(())
+    }
+
+    pub fn set_format(&self, format: LogFormat) {
+        self.inner.write().unwrap_or_else(|e| e.into_inner()).format = format;
+    }
+
+    pub fn set_level(&self, level: LevelFilter) {
+        if self.installed.load(Ordering::Relaxed) {
+            log::set_max_level(level);
+        }
+    }
+
+    // context field added to every following record
+    pub fn field<V: fmt::Display>(&self, key: &str, value: V) {
+        self.inner.write().unwrap_or_else(|e| e.into_inner())
+            .fields.insert(key.to_string(), value.to_string());
+    }
+
+    pub fn remove_field(&self, key: &str) {
+        self.inner.write().unwrap_or_else(|e| e.into_inner()).fields.remove(key);
+    }
+
+    pub fn format(&self, record: &Record) -> String {
+        let inner = self.inner.read().unwrap_or_else(|e| e.into_inner());
+        let mut kvs = Fields(Vec::new());
+        let _ = record.key_values().visit(&mut kvs);
+
+        match inner.format {
+            LogFormat::Text => {
+                let level = color::paint(Style::level(record.level()),
+                                         format!("{:<5}", record.level()),
+                                         color::enabled(Stream::Stderr));
+                let mut line = format!("{} {} [{}] {}", timestamp(SystemTime::now()),
+                                       level, record.target(), record.args());
+                for (k, v) in inner.fields.iter() {
+                    line.push_str(&format!(" {}={}", k, v));
+                }
+                for (k, v) in kvs.0 {
+                    line.push_str(&format!(" {}={}", k, v));
+                }
+                line
+            }
+
+            LogFormat::Json => {
+                let mut obj = Map::new();
+                obj.insert("timestamp".into(), timestamp(SystemTime::now()).into());
+                obj.insert("level".into(), record.level().as_str().into());
+                obj.insert("target".into(), record.target().into());
+                obj.insert("message".into(), record.args().to_string().into());
+                obj.insert("pid".into(), self.pid.into());
+                obj.insert("name".into(), inner.name.clone().into());
+                obj.insert("version".into(), inner.version.clone().into());
+
+                let mut fields = Map::new();
+                for (k, v) in inner.fields.iter() {
+                    fields.insert(k.clone(), v.clone().into());
+                }
+                for (k, v) in kvs.0 {
+                    fields.insert(k, v);
+                }
+                if !fields.is_empty() {
+                    obj.insert("fields".into(), Value::Object(fields));
+                }
+
+                Value::Object(obj).to_string()
+            }
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            let line = self.format(record);
+            let _ = writeln!(std::io::stderr().lock(), "{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+struct Fields(Vec<(String, Value)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>)
+                  -> Result<(), kv::Error> {
+        let value = if let Some(v) = value.to_bool() {
+            Value::from(v)
+        } else if let Some(v) = value.to_i64() {
+            Value::from(v)
+        } else if let Some(v) = value.to_u64() {
+            Value::from(v)
+        } else if let Some(v) = value.to_f64() {
+            Value::from(v)
+        } else {
+            Value::from(value.to_string())
+        };
+
+        self.0.push((key.to_string(), value));
+        Ok(())
+    }
+}
+
+// Install the process wide logger, the first caller wins.
+pub fn init(name: &str, version: &str) -> &'static Logger {
+    let mut installed = false;
+    let logger = LOGGER.get_or_init(|| {
+        installed = true;
+        Logger::new(name, version)
+    });
+
+    if installed && log::set_logger(logger).is_ok() {
+        logger.installed.store(true, Ordering::Relaxed);
+        log::set_max_level(LevelFilter::Info);
+    }
+
+    logger
+}
+
+pub fn logger() -> Option<&'static Logger> {
+    LOGGER.get()
+}
+
+// RFC 3339 in UTC with milliseconds, e.g. 2019-09-01T08:00:00.000Z
+pub fn timestamp(time: SystemTime) -> String {
+    let d = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = d.as_secs() as i64;
+    let (days, rem) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+
+    let (year, month, day) = build::civil_from_days(days);
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+            year, month, day, rem / 3600, rem % 3600 / 60, rem % 60, d.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use log::Level;
+
+    #[test]
+    fn timestamp_utc() {
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(timestamp(UNIX_EPOCH + Duration::from_millis(1_567_324_800_123)),
+                   "2019-09-01T08:00:00.123Z");
+    }
+
+    #[test]
+    fn json_line() {
+        let logger = Logger::new("myapp", "abc1234");
+        logger.set_format(LogFormat::Json);
+        logger.field("dc", "sh-1");
+
+        let kvs: &[(&str, i64)] = &[("port", 8080)];
+        let line = logger.format(&Record::builder()
+                                 .args(format_args!("listening"))
+                                 .level(Level::Info)
+                                 .target("myapp::server")
+                                 .key_values(&kvs)
+                                 .build());
+
+        let v: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(v["level"], "INFO");
+        assert_eq!(v["target"], "myapp::server");
+        assert_eq!(v["message"], "listening");
+        assert_eq!(v["name"], "myapp");
+        assert_eq!(v["version"], "abc1234");
+        assert_eq!(v["pid"], std::process::id());
+        assert_eq!(v["fields"]["dc"], "sh-1");
+        assert_eq!(v["fields"]["port"], 8080);
+    }
+
+    #[test]
+    fn config_format() {
+        let mut c = config::Config::default();
+        c.set("log.format", "json").unwrap();
+        c.set("log.level", "debug").unwrap();
+        let cfg: LogConfig = c.get("log").unwrap();
+        assert_eq!(cfg.format, LogFormat::Json);
+        assert_eq!(cfg.level, "debug");
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
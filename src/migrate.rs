@@ -0,0 +1,263 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, HashMap};
+use config::{Config, ConfigError, FileFormat};
+use serde_json::Value;
+
+use super::error::{YiError, YiErrorKind, YiResult, YiResultExt};
+
+// key holding the layout version of a config file, missing means 0
+pub const VERSION_KEY: &str = "version";
+
+// extensions tried by `config::File::with_name`
+const EXTENSIONS: &[&str] = &["toml", "json", "yaml", "yml", "hjson", "ini"];
+
+// Upgrade the config from `version` to `version + 1` in place.
+pub type Migration = fn(&mut Value) -> YiResult<()>;
+
+// Layout versions of one config section, registered on the App with
+// `with_migrations`:
+//
+//     Migrations::new(2)
+//         .step(0, |c| { c["listen"] = c["bind"].take(); Ok(()) })
+//         .step(1, |c| { c["log"]["format"] = "json".into(); Ok(()) })
+//         .write_back(true)
+#[derive(Debug, Clone, Default)]
+pub struct Migrations {
+    current: u32,
+    steps: BTreeMap<u32, Migration>,
+    write_back: bool,
+}
+
+impl Migrations {
+    pub fn new(current: u32) -> Self {
+        Migrations { current, ..Default::default() }
+    }
+
+    pub fn step(mut self, from: u32, migration: Migration) -> Self {
+        self.steps.insert(from, migration);
+        self
+    }
+
+    // keep a `<file>.v<old>.bak` and store the upgraded file
+    pub fn write_back(mut self, on: bool) -> Self {
+        self.write_back = on;
+        self
+    }
+
+    pub fn current(&self) -> u32 {
+        self.current
+    }
+
+    // Returns the version the value had, `Ver` if it is newer than `current`.
+    pub fn migrate(&self, value: &mut Value) -> YiResult<u32> {
+        let found = match value.get(VERSION_KEY) {
+            None                     => 0,
+            // negative, fractional or beyond u32
+            Some(Value::Number(n))   => n.as_u64().and_then(|n| u32::try_from(n).ok())
+                .ok_or_else(|| YiError::from(format!("invalid config version: {}", n)))?,
+            Some(Value::String(s))   => s.trim().parse()
+                .map_err(|_| YiError::from(format!("invalid config version: {}", s)))?,
+            Some(v)                  => return Err(format!("invalid config version: {}", v).into()),
+        };
+
+        if found > self.current {
+            return Err(YiErrorKind::Ver {
+                found: found.to_string(),
+                required: format!("<={}", self.current),
+            }.into());
+        }
+
+        for v in found..self.current {
+            let step = self.steps.get(&v)
+                .ok_or_else(|| YiError::from(format!("no config migration from version {}", v)))?;
+            step(value)?;
+            log::info!("config migrated from version {} to {}", v, v + 1);
+        }
+
+        if let Value::Object(map) = value {
+            map.insert(VERSION_KEY.to_string(), self.current.into());
+        }
+
+        Ok(found)
+    }
+
+    // Source of `name`, found the way `config::File::with_name` would, for
+    // `Config::merge`. It is read and migrated again on every `refresh`.
+    pub fn load(&self, name: &str) -> Migrated {
+        Migrated { name: name.to_string(), migrations: self.clone() }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Migrated {
+    name: String,
+    migrations: Migrations,
+}
+
+impl Migrated {
+    fn value(&self) -> YiResult<Value> {
+        let path = find(&self.name).ok_or_else(|| {
+            YiError::from(format!("configuration file \"{}\" not found", self.name))
+        })?;
+
+        let mut value = read(&path)?;
+        let found = self.migrations.migrate(&mut value)?;
+
+        if self.migrations.write_back && found < self.migrations.current {
+            write(&path, found, &value)?;
+        }
+        Ok(value)
+    }
+}
+
+// errors come out as `ConfigError::Foreign` like those of other sources
+impl config::Source for Migrated {
+    fn clone_into_box(&self) -> Box<dyn config::Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, config::Value>, ConfigError> {
+        let value = self.value().map_err(|e| ConfigError::Foreign(Box::new(e)))?;
+        config::File::from_str(&value.to_string(), FileFormat::Json).collect()
+    }
+}
+
+fn find(name: &str) -> Option<PathBuf> {
+    let path = PathBuf::from(name);
+    if path.is_file() {
+        return Some(path);
+    }
+
+    EXTENSIONS.iter()
+        .map(|ext| path.with_extension(ext))
+        .find(|p| p.is_file())
+}
+
+fn ext(path: &Path) -> &str {
+    path.extension().and_then(|e| e.to_str()).unwrap_or("")
+}
+
+// json and toml keep the case of keys, other formats go through config
+fn read(path: &Path) -> YiResult<Value> {
+    let text = fs::read_to_string(path)?;
+
+    match ext(path) {
+        "json" => serde_json::from_str(&text).to_yierr(path.display()),
+        "toml" => {
+            let v: toml::Value = toml::from_str(&text).to_yierr(path.display())?;
+            serde_json::to_value(v).to_yierr(path.display())
+        }
+        _ => {
+            let mut c = Config::default();
+            c.merge(config::File::from(path)).to_yierr(path.display())?;
+            c.try_into().to_yierr(path.display())
+        }
+    }
+}
+
+fn write(path: &Path, found: u32, value: &Value) -> YiResult<()> {
+    let text = match ext(path) {
+        "json" => serde_json::to_string_pretty(value).to_yierr(path.display())?,
+        "toml" => {
+            let v = toml::Value::try_from(value).to_yierr(path.display())?;
+            toml::to_string_pretty(&v).to_yierr(path.display())?
+        }
+        e => {
+            log::warn!("{} files are not written back, {} is migrated in memory only",
+                       e, path.display());
+            return Ok(());
+        }
+    };
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".v{}.bak", found));
+    fs::copy(path, &backup).to_yierr(path.display())?;
+    fs::write(path, text).to_yierr(path.display())?;
+    log::info!("{} upgraded, previous version kept in {}",
+               path.display(), Path::new(&backup).display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn rename(c: &mut Value) -> YiResult<()> {
+        c["listen"] = c["bind"].take();
+        c.as_object_mut().map(|m| m.remove("bind"));
+        Ok(())
+    }
+
+    fn migrations() -> Migrations {
+        Migrations::new(2)
+            .step(0, rename)
+            .step(1, |c| { c["workers"] = 4.into(); Ok(()) })
+    }
+
+    #[test]
+    fn in_memory() {
+        let mut v = serde_json::json!({ "bind": "0.0.0.0:80" });
+        assert_eq!(migrations().migrate(&mut v).unwrap(), 0);
+        assert_eq!(v, serde_json::json!({ "listen": "0.0.0.0:80", "workers": 4, "version": 2 }));
+
+        let mut v = serde_json::json!({ "version": "1", "listen": "x" });
+        assert_eq!(migrations().migrate(&mut v).unwrap(), 1);
+        assert_eq!(v["workers"], 4);
+    }
+
+    #[test]
+    fn newer_file() {
+        let mut v = serde_json::json!({ "version": 3 });
+        match migrations().migrate(&mut v).unwrap_err().kind() {
+            YiErrorKind::Ver { found, required } => {
+                assert_eq!(found, "3");
+                assert_eq!(required, "<=2");
+            }
+            k => panic!("unexpected {:?}", k),
+        }
+
+        let mut v = serde_json::json!({});
+        assert!(Migrations::new(1).migrate(&mut v).is_err());
+
+        for version in [serde_json::json!(1u64 << 32), serde_json::json!(-1), serde_json::json!(1.5)] {
+            let mut v = serde_json::json!({ "version": version });
+            let e = migrations().migrate(&mut v).unwrap_err().to_string();
+            assert!(e.contains("invalid config version"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn write_back() {
+        let dir = env::temp_dir().join(format!("yiapp-migrate-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("app.toml");
+        fs::write(&file, "bind = \"0.0.0.0:80\"\n").unwrap();
+
+        let name = dir.join("app");
+        let mut c = Config::default();
+        c.merge(migrations().write_back(true).load(name.to_str().unwrap())).unwrap();
+        assert_eq!(c.get_str("listen").unwrap(), "0.0.0.0:80");
+
+        assert_eq!(fs::read_to_string(dir.join("app.toml.v0.bak")).unwrap(),
+                   "bind = \"0.0.0.0:80\"\n");
+        let upgraded: toml::Value = toml::from_str(&fs::read_to_string(&file).unwrap()).unwrap();
+        assert_eq!(upgraded["version"].as_integer(), Some(2));
+        assert_eq!(upgraded["workers"].as_integer(), Some(4));
+
+        // read again on refresh, not frozen at the first load
+        fs::write(&file, "version = 2\nlisten = \"0.0.0.0:81\"\n").unwrap();
+        c.refresh().unwrap();
+        assert_eq!(c.get_str("listen").unwrap(), "0.0.0.0:81");
+
+        fs::write(&file, "version = 3\n").unwrap();
+        let e = c.refresh().to_yikind(crate::arg::Error::File).unwrap_err();
+        assert_eq!(e.exit_code(), crate::error::exit::CONFIG);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,530 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use super::error::{YiError, YiErrorKind, YiResult, YiResultExt};
+use super::code;
+#[cfg(all(unix, feature = "systemd"))]
+use super::systemd;
+
+// `rpc` section of a config file:
+//
+//     [rpc]
+//     bind = "127.0.0.1:7000"         # or "unix:/run/app.sock", "systemd:<name>"
+//     connect_timeout_ms = 3000
+//     timeout_ms = 30000              # read and write, 0 waits forever
+//     max_frame = 16777216
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RpcConfig {
+    pub bind: String,
+    pub connect_timeout_ms: u64,
+    pub timeout_ms: u64,
+    pub max_frame: usize,
+}
+
+impl Default for RpcConfig {
+    fn default() -> Self {
+        RpcConfig {
+            bind: "127.0.0.1:0".to_string(),
+            connect_timeout_ms: 3_000,
+            timeout_ms: 30_000,
+            max_frame: 16 << 20,
+        }
+    }
+}
+
+impl RpcConfig {
+    fn timeout(&self) -> Option<Duration> {
+        Some(Duration::from_millis(self.timeout_ms)).filter(|d| !d.is_zero())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    Bind,
+    Connect,
+    Frame,
+    Remote,
+    Config,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Bind    => write!(f, "rpc bind"),
+            Error::Connect => write!(f, "rpc connect"),
+            Error::Frame   => write!(f, "rpc frame"),
+            Error::Remote  => write!(f, "rpc remote error"),
+            Error::Config  => write!(f, "rpc configuration"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Bind    => "YI-RPC-001",
+            Error::Connect => "YI-RPC-002",
+            Error::Frame   => "YI-RPC-003",
+            Error::Remote  => "YI-RPC-004",
+            Error::Config  => "YI-RPC-005",
+        }
+    }
+}
+
+impl From<Error> for YiErrorKind {
+    fn from(e: Error) -> YiErrorKind {
+        YiErrorKind::Code(e.code(), e.to_string())
+    }
+}
+
+impl From<Error> for YiError {
+    fn from(e: Error) -> YiError {
+        YiError::from(YiErrorKind::from(e))
+    }
+}
+
+// A typed service, one response or error per request.
+pub trait Service: Send + Sync + 'static {
+    type Request: Serialize + DeserializeOwned;
+    type Response: Serialize + DeserializeOwned;
+
+    fn call(&self, req: Self::Request) -> YiResult<Self::Response>;
+}
+
+// A YiError on the wire, the client turns it back into one with the same
+// code, chain, fields and hints.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Remote {
+    pub code: String,
+    pub message: String,
+    pub causes: Vec<String>,
+    pub fields: BTreeMap<String, String>,
+    pub hints: Vec<String>,
+}
+
+impl From<&YiError> for Remote {
+    fn from(e: &YiError) -> Remote {
+        let mut causes = e.chain();
+        let message = if causes.is_empty() { String::new() } else { causes.remove(0) };
+        let fields = e.fields().into_iter()
+            .map(|(k, v)| match v {
+                Value::String(s) => (k, s),
+                v                => (k, v.to_string()),
+            })
+            .collect();
+
+        Remote { code: e.code().to_string(), message, causes, fields, hints: e.hints() }
+    }
+}
+
+impl From<Remote> for YiError {
+    fn from(r: Remote) -> YiError {
+        let source = r.causes.into_iter().rev().fold(None, |source, c| {
+            let kind = YiErrorKind::Info(c);
+            Some(match source {
+                Some(s) => YiError::new(kind, s),
+                None    => YiError::from(kind),
+            })
+        });
+
+        // codes unknown on this side keep their text in a field
+        let (code, unknown) = match code::lookup(&r.code) {
+            Some(c) => (c.code, None),
+            None    => (Error::Remote.code(), Some(r.code)),
+        };
+        let kind = YiErrorKind::Code(code, r.message);
+        let mut e = match source {
+            Some(s) => YiError::new(kind, s),
+            None    => YiError::from(kind),
+        };
+
+        if let Some(c) = unknown {
+            e = e.with_field("remote_code", c);
+        }
+        for (k, v) in r.fields {
+            e = e.with_field(&k, v);
+        }
+        r.hints.into_iter().fold(e, |e, h| e.hint(h))
+    }
+}
+
+enum Endpoint {
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(String),
+    // a socket passed by systemd socket activation
+    #[cfg(all(unix, feature = "systemd"))]
+    Systemd(String),
+}
+
+impl Endpoint {
+    fn parse(bind: &str) -> Self {
+        #[cfg(all(unix, feature = "systemd"))]
+        if let Some(name) = bind.strip_prefix("systemd:") {
+            return Endpoint::Systemd(name.to_string());
+        }
+        match bind.strip_prefix("unix:") {
+            #[cfg(unix)]
+            Some(path) => Endpoint::Unix(path.to_string()),
+            _          => Endpoint::Tcp(bind.to_string()),
+        }
+    }
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s)  => s.set_read_timeout(timeout).and(s.set_write_timeout(timeout)),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.set_read_timeout(timeout).and(s.set_write_timeout(timeout)),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s)  => s.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(s)  => s.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(s)  => s.flush(),
+            #[cfg(unix)]
+            Stream::Unix(s) => s.flush(),
+        }
+    }
+}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, String),
+}
+
+impl Listener {
+    fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(l)     => l.accept().map(|(s, _)| Stream::Tcp(s)),
+            #[cfg(unix)]
+            Listener::Unix(l, _) => l.accept().map(|(s, _)| Stream::Unix(s)),
+        }
+    }
+}
+
+// Frames are a 4 byte big endian length and a bincode payload. `None` is a
+// connection closed between two frames.
+fn read_frame<R: Read>(r: &mut R, max: usize) -> YiResult<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    match r.read_exact(&mut len) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        r => r.to_yikind(Error::Frame)?,
+    }
+
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max {
+        return Err(YiError::from(Error::Frame)
+                   .with_field("len", len)
+                   .with_field("max_frame", max)
+                   .hint("raise max_frame in the rpc config of both sides"));
+    }
+
+    let mut buf = vec![0; len];
+    r.read_exact(&mut buf).to_yikind(Error::Frame)?;
+    Ok(Some(buf))
+}
+
+fn write_frame<W: Write, M: Serialize>(w: &mut W, msg: &M, max: usize) -> YiResult<()> {
+    let buf = bincode::serialize(msg).to_yikind(Error::Frame)?;
+    if buf.len() > max {
+        return Err(YiError::from(Error::Frame)
+                   .with_field("len", buf.len())
+                   .with_field("max_frame", max));
+    }
+
+    w.write_all(&(buf.len() as u32).to_be_bytes()).to_yikind(Error::Frame)?;
+    w.write_all(&buf).to_yikind(Error::Frame)?;
+    w.flush().to_yikind(Error::Frame)
+}
+
+pub struct Server<S> {
+    config: RpcConfig,
+    service: Arc<S>,
+    listener: Listener,
+    addr: String,
+}
+
+impl<S: Service> Server<S> {
+    pub fn bind(config: &RpcConfig, service: S) -> YiResult<Self> {
+        let (listener, addr) = match Endpoint::parse(&config.bind) {
+            Endpoint::Tcp(addr) => {
+                let l = TcpListener::bind(&addr).to_yikind(Error::Bind).with_field("bind", &addr)?;
+                let local = l.local_addr().to_yikind(Error::Bind)?.to_string();
+                (Listener::Tcp(l), local)
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                // a socket file left by a dead server, never another kind of file
+                let socket = fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket());
+                if socket && UnixStream::connect(&path).is_err() {
+                    let _ = fs::remove_file(&path);
+                }
+                let l = UnixListener::bind(&path).to_yikind(Error::Bind)
+                    .with_field("bind", &path)
+                    .hint("is another instance running?")?;
+                (Listener::Unix(l, path.clone()), format!("unix:{}", path))
+            }
+            #[cfg(all(unix, feature = "systemd"))]
+            Endpoint::Systemd(name) => match systemd::take(&name).to_yikind(Error::Bind)? {
+                systemd::Socket::Tcp(l)  => (Listener::Tcp(l), config.bind.clone()),
+                // the socket file belongs to systemd, it is left on drop
+                systemd::Socket::Unix(l) => (Listener::Unix(l, String::new()), config.bind.clone()),
+            },
+        };
+
+        Ok(Server { config: config.clone(), service: Arc::new(service), listener, addr })
+    }
+
+    // the bound address in `bind` syntax, with the port picked for ":0"
+    pub fn local_addr(&self) -> &str {
+        &self.addr
+    }
+
+    // Accept connections until the listener fails, one thread per connection.
+    pub fn serve(self) -> YiResult<()> {
+        log::info!("rpc listening on {}", self.addr);
+        loop {
+            let stream = match self.listener.accept() {
+                Ok(s)  => s,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    return Err(YiError::new(Error::Bind.into(), e).with_field("bind", &self.addr));
+                }
+            };
+
+            let service = self.service.clone();
+            let config = self.config.clone();
+            thread::spawn(move || {
+                if let Err(e) = Self::connection(stream, &*service, &config) {
+                    log::warn!("rpc connection: {:#}", e);
+                }
+            });
+        }
+    }
+
+    pub fn spawn(self) -> thread::JoinHandle<YiResult<()>> {
+        thread::spawn(move || self.serve())
+    }
+
+    fn connection(mut stream: Stream, service: &S, config: &RpcConfig) -> YiResult<()> {
+        stream.set_timeout(config.timeout())?;
+
+        while let Some(buf) = read_frame(&mut stream, config.max_frame)? {
+            let reply = match bincode::deserialize(&buf).to_yikind(Error::Frame) {
+                Ok(req) => service.call(req).map_err(|e| Remote::from(&e)),
+                Err(e)  => Err(Remote::from(&e)),
+            };
+            write_frame(&mut stream, &reply, config.max_frame)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl<S> Drop for Server<S> {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = &self.listener {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// One connection to a `Server`, calls are sent one after the other.
+pub struct Client<Req, Resp> {
+    config: RpcConfig,
+    stream: Stream,
+    peer: String,
+    _types: PhantomData<fn(Req) -> Resp>,
+}
+
+impl<Req: Serialize, Resp: DeserializeOwned> Client<Req, Resp> {
+    pub fn connect(config: &RpcConfig) -> YiResult<Self> {
+        let peer = config.bind.clone();
+        let stream = match Endpoint::parse(&config.bind) {
+            Endpoint::Tcp(addr) => {
+                let timeout = Duration::from_millis(config.connect_timeout_ms.max(1));
+                let addrs: Vec<_> = addr.to_socket_addrs().to_yikind(Error::Connect)
+                    .with_field("peer", &peer)?
+                    .collect();
+                let mut last = None;
+                let stream = addrs.iter().find_map(|a| {
+                    TcpStream::connect_timeout(a, timeout).map_err(|e| last = Some(e)).ok()
+                });
+                match (stream, last) {
+                    (Some(s), _)    => Stream::Tcp(s),
+                    (None, Some(e)) => return Err(YiError::new(Error::Connect.into(), e)
+                                                  .with_field("peer", &peer)),
+                    (None, None)    => return Err(YiError::from(Error::Connect)
+                                                  .with_field("peer", &peer)),
+                }
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                Stream::Unix(UnixStream::connect(&path).to_yikind(Error::Connect)
+                             .with_field("peer", &peer)?)
+            }
+            #[cfg(all(unix, feature = "systemd"))]
+            Endpoint::Systemd(_) => {
+                return Err(YiError::from(Error::Connect).with_field("peer", &peer)
+                           .hint("systemd sockets are for servers, connect to their address"));
+            }
+        };
+        stream.set_timeout(config.timeout()).to_yikind(Error::Connect)?;
+
+        Ok(Client { config: config.clone(), stream, peer, _types: PhantomData })
+    }
+
+    pub fn peer(&self) -> &str {
+        &self.peer
+    }
+
+    pub fn call(&mut self, req: &Req) -> YiResult<Resp> {
+        write_frame(&mut self.stream, req, self.config.max_frame)
+            .with_field("peer", &self.peer)?;
+        let buf = read_frame(&mut self.stream, self.config.max_frame)
+            .with_field("peer", &self.peer)?
+            .ok_or_else(|| YiError::from(Error::Frame).with_field("peer", &self.peer)
+                        .hint("the server closed the connection"))?;
+
+        let reply: Result<Resp, Remote> = bincode::deserialize(&buf).to_yikind(Error::Frame)
+            .with_field("peer", &self.peer)?;
+        reply.map_err(|r| YiError::from(r).with_field("peer", &self.peer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    struct Calc;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    enum Op { Add(i64, i64), Div(i64, i64) }
+
+    impl Service for Calc {
+        type Request = Op;
+        type Response = i64;
+
+        fn call(&self, req: Op) -> YiResult<i64> {
+            match req {
+                Op::Add(a, b) => Ok(a + b),
+                Op::Div(_, 0) => Err(YiError::from(YiErrorKind::Opt("division by zero".into()))
+                                     .with_field("divisor", 0)
+                                     .hint("pick another divisor")),
+                Op::Div(a, b) => Ok(a / b),
+            }
+        }
+    }
+
+    fn roundtrip(bind: &str) {
+        let config = RpcConfig { bind: bind.to_string(), ..Default::default() };
+        let server = Server::bind(&config, Calc).unwrap();
+        let config = RpcConfig { bind: server.local_addr().to_string(), ..config };
+        server.spawn();
+
+        let mut client = Client::<Op, i64>::connect(&config).unwrap();
+        assert_eq!(client.call(&Op::Add(2, 3)).unwrap(), 5);
+        assert_eq!(client.call(&Op::Div(9, 3)).unwrap(), 3);
+
+        let e = client.call(&Op::Div(1, 0)).unwrap_err();
+        assert_eq!(e.code(), "YI-CLI-004");
+        assert_eq!(e.chain()[0], "division by zero");
+        assert_eq!(e.fields()["divisor"], "0");
+        assert_eq!(e.hints(), vec!["pick another divisor"]);
+        assert_eq!(client.call(&Op::Add(1, 1)).unwrap(), 2);
+    }
+
+    #[test]
+    fn tcp() {
+        roundtrip("127.0.0.1:0");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix() {
+        let path = env::temp_dir().join(format!("yiapp-rpc-{}.sock", process::id()));
+        roundtrip(&format!("unix:{}", path.display()));
+
+        // a regular file in the way is left alone
+        let path = env::temp_dir().join(format!("yiapp-rpc-{}.txt", process::id()));
+        fs::write(&path, "data").unwrap();
+        let config = RpcConfig { bind: format!("unix:{}", path.display()), ..Default::default() };
+        assert_eq!(Server::bind(&config, Calc).err().unwrap().code(), "YI-RPC-001");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "data");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn wire_errors() {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, &vec![0u8; 64], 1024).unwrap();
+        assert!(read_frame(&mut &buf[..], 16).is_err());
+        assert_eq!(read_frame(&mut &buf[..], 1024).unwrap().map(|b| b.len()), Some(72));
+        assert!(read_frame(&mut &buf[..0], 1024).unwrap().is_none());
+
+        let remote = Remote {
+            code: "SHOP-X-001".into(),
+            message: "out of stock".into(),
+            causes: vec!["sku 7".into()],
+            fields: BTreeMap::new(),
+            hints: Vec::new(),
+        };
+        let e = YiError::from(remote.clone());
+        assert_eq!(e.code(), "YI-RPC-004");
+        assert_eq!(e.fields()["remote_code"], "SHOP-X-001");
+        assert_eq!(Remote::from(&e).causes, remote.causes);
+
+        let config = RpcConfig { bind: "127.0.0.1:1".into(), ..Default::default() };
+        let e = Client::<Op, i64>::connect(&config).err().unwrap();
+        assert_eq!(e.code(), "YI-RPC-002");
+    }
+}
@@ -0,0 +1,428 @@
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::error::{exit, YiError, YiResult};
+
+// default time from the shutdown request to the forced exit
+pub const GRACE: Duration = Duration::from_secs(10);
+
+static COORDINATOR: OnceLock<Coordinator> = OnceLock::new();
+
+// Set once when the process should stop, by SIGTERM, SIGINT, the admin
+// `shutdown` command or `main` returning. Cheap to clone into workers:
+//
+//     let token = app.shutdown();
+//     while !token.wait_timeout(Duration::from_secs(1)) { poll(); }
+//
+// Without a token taken the process exits once the hooks are done.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<(Mutex<Option<String>>, Condvar)>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.reason().is_some()
+    }
+
+    // what triggered it, e.g. "SIGTERM"
+    pub fn reason(&self) -> Option<String> {
+        self.inner.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn wait(&self) {
+        let (lock, cvar) = &*self.inner;
+        let guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let _guard = cvar.wait_while(guard, |r| r.is_none()).unwrap_or_else(|e| e.into_inner());
+    }
+
+    // true once triggered, false after `timeout`
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let (lock, cvar) = &*self.inner;
+        let guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let (guard, _) = cvar.wait_timeout_while(guard, timeout, |r| r.is_none())
+            .unwrap_or_else(|e| e.into_inner());
+        guard.is_some()
+    }
+
+    // false if it was triggered before
+    fn trigger(&self, reason: &str) -> bool {
+        let (lock, cvar) = &*self.inner;
+        let mut guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        if guard.is_some() {
+            return false;
+        }
+        *guard = Some(reason.to_string());
+        cvar.notify_all();
+        true
+    }
+}
+
+struct Hook {
+    name: String,
+    timeout: Duration,
+    run: Box<dyn FnOnce() + Send>,
+}
+
+type Reload = Box<dyn Fn() -> YiResult<()> + Send + Sync>;
+
+struct Coordinator {
+    token: Shutdown,
+    // a token was handed out, `main` returns by itself
+    watched: AtomicBool,
+    done: Shutdown,
+    grace_ms: AtomicU64,
+    hooks: Mutex<Vec<Hook>>,
+    reload: Mutex<Vec<Reload>>,
+}
+
+fn coordinator() -> &'static Coordinator {
+    COORDINATOR.get_or_init(|| Coordinator {
+        token: Shutdown::new(),
+        watched: AtomicBool::new(false),
+        done: Shutdown::new(),
+        grace_ms: AtomicU64::new(GRACE.as_millis() as u64),
+        hooks: Mutex::new(Vec::new()),
+        reload: Mutex::new(Vec::new()),
+    })
+}
+
+pub fn token() -> Shutdown {
+    let c = coordinator();
+    c.watched.store(true, Ordering::Relaxed);
+    c.token.clone()
+}
+
+// the token for the crate itself, doesn't count as `main` watching
+pub(crate) fn internal_token() -> Shutdown {
+    coordinator().token.clone()
+}
+
+pub fn set_grace(grace: Duration) {
+    coordinator().grace_ms.store(grace.as_millis() as u64, Ordering::Relaxed);
+}
+
+pub fn grace() -> Duration {
+    Duration::from_millis(coordinator().grace_ms.load(Ordering::Relaxed))
+}
+
+// Run `f` on shutdown, hooks run one after the other in registration order,
+// a hook still running after `timeout` is left behind.
+pub fn hook<F: FnOnce() + Send + 'static>(name: &str, timeout: Duration, f: F) {
+    coordinator().hooks.lock().unwrap_or_else(|e| e.into_inner()).push(Hook {
+        name: name.to_string(),
+        timeout,
+        run: Box::new(f),
+    });
+}
+
+// run by SIGHUP and the admin `reload` command
+pub fn on_reload<F: Fn() -> YiResult<()> + Send + Sync + 'static>(f: F) {
+    coordinator().reload.lock().unwrap_or_else(|e| e.into_inner()).push(Box::new(f));
+}
+
+pub fn reload() -> YiResult<()> {
+    let reload = coordinator().reload.lock().unwrap_or_else(|e| e.into_inner());
+    if reload.is_empty() {
+        return Err(YiError::from("nothing to reload").hint("spawn the admin socket, or use \
+                                                            shutdown::on_reload"));
+    }
+    reload.iter().try_for_each(|f| f())
+}
+
+// Request the shutdown: run the hooks on their own thread and exit the
+// process with `exit::FAILURE` if it is still alive after the grace period.
+pub fn trigger(reason: &str) {
+    request(reason, None)
+}
+
+// `trigger`, and exit with `code` once the hooks are done when nobody took a
+// `token()`, e.g. 128 + signal number for SIGTERM.
+pub(crate) fn stop(reason: &str, code: i32) {
+    request(reason, Some(code))
+}
+
+fn request(reason: &str, code: Option<i32>) {
+    let c = coordinator();
+    if !c.token.trigger(reason) {
+        return;
+    }
+
+    let grace = grace();
+    if reason == "exit" {
+        log::debug!("shutdown, grace period {}ms", grace.as_millis());
+    } else {
+        log::info!("shutdown requested by {}, grace period {}ms", reason, grace.as_millis());
+    }
+
+    thread::spawn(move || {
+        thread::sleep(grace);
+        log::error!("shutdown did not finish within {}ms, exiting", grace.as_millis());
+        log::logger().flush();
+        process::exit(exit::FAILURE);
+    });
+
+    thread::spawn(move || {
+        run_hooks();
+        c.done.trigger("hooks");
+        if let Some(code) = code.filter(|_| !c.watched.load(Ordering::Relaxed)) {
+            log::logger().flush();
+            process::exit(code);
+        }
+    });
+}
+
+// Called by `App::run` when `main` returned: trigger the shutdown if nobody
+// did and wait for the hooks.
+pub fn finish() {
+    trigger("exit");
+    coordinator().done.wait();
+}
+
+fn run_hooks() {
+    let hooks: Vec<Hook> = coordinator().hooks.lock().unwrap_or_else(|e| e.into_inner())
+        .drain(..).collect();
+
+    for hook in hooks {
+        let start = Instant::now();
+        let (tx, rx) = mpsc::channel();
+        let run = hook.run;
+        thread::spawn(move || {
+            run();
+            let _ = tx.send(());
+        });
+
+        match rx.recv_timeout(hook.timeout) {
+            Ok(())                                  =>
+                log::info!("shutdown hook {} done in {}ms", hook.name, start.elapsed().as_millis()),
+            Err(mpsc::RecvTimeoutError::Timeout)    =>
+                log::warn!("shutdown hook {} timed out after {}ms", hook.name,
+                           hook.timeout.as_millis()),
+            Err(mpsc::RecvTimeoutError::Disconnected) =>
+                log::error!("shutdown hook {} panicked", hook.name),
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use self::signal::install;
+
+// SIGTERM and SIGINT trigger the shutdown, a second one exits at once,
+// SIGHUP reloads. The handler only writes the signal number to a pipe,
+// a thread does the rest.
+#[cfg(unix)]
+mod signal {
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::unix::io::FromRawFd;
+    use std::process;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::thread;
+    use libc::c_int;
+
+    use crate::error::{exit, YiResult, YiResultExt};
+
+    static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+    extern "C" fn handler(sig: c_int) {
+        let fd = PIPE.load(Ordering::Relaxed);
+        if fd >= 0 {
+            let byte = sig as u8;
+            unsafe {
+                #[cfg(target_os = "linux")]
+                let errno = *libc::__errno_location();
+                libc::write(fd, &byte as *const u8 as *const libc::c_void, 1);
+                #[cfg(target_os = "linux")]
+                { *libc::__errno_location() = errno; }
+            }
+        }
+    }
+
+    fn name(sig: c_int) -> &'static str {
+        match sig {
+            libc::SIGTERM => "SIGTERM",
+            libc::SIGINT  => "SIGINT",
+            libc::SIGHUP  => "SIGHUP",
+            _             => "signal",
+        }
+    }
+
+    pub fn install() -> YiResult<()> {
+        if PIPE.load(Ordering::Relaxed) >= 0 {
+            return Ok(());
+        }
+
+        let mut fds = [0 as c_int; 2];
+        unsafe {
+            if libc::pipe(fds.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error()).to_yierr("signal pipe");
+            }
+            // not inherited by `spwan`ed children
+            for fd in &fds {
+                libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+        }
+        PIPE.store(fds[1], Ordering::Relaxed);
+
+        for sig in &[libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = handler as *const () as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                if libc::sigaction(*sig, &action, std::ptr::null_mut()) != 0 {
+                    return Err(io::Error::last_os_error()).to_yierr(name(*sig));
+                }
+            }
+        }
+
+        let mut pipe = unsafe { File::from_raw_fd(fds[0]) };
+        thread::Builder::new().name("signals".to_string()).spawn(move || {
+            let mut byte = [0u8];
+            while pipe.read_exact(&mut byte).is_ok() {
+                let sig = c_int::from(byte[0]);
+                match sig {
+                    libc::SIGHUP => match super::reload() {
+                        Ok(())  => log::info!("SIGHUP: config reloaded"),
+                        Err(e)  => log::error!("SIGHUP: {:#}", e),
+                    },
+                    _ if super::internal_token().is_triggered() => {
+                        log::warn!("{} during shutdown, exiting", name(sig));
+                        log::logger().flush();
+                        process::exit(exit::FAILURE);
+                    }
+                    _ => super::stop(name(sig), 128 + sig),
+                }
+            }
+        }).to_yierr("signal thread")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use libc::c_int;
+
+    const CHILD: &str = "YIAPP_SHUTDOWN_CHILD";
+
+    #[test]
+    fn token() {
+        let t = Shutdown::new();
+        assert!(!t.wait_timeout(Duration::from_millis(10)));
+        let t2 = t.clone();
+        thread::spawn(move || t2.trigger("test"));
+        t.wait();
+        assert_eq!(t.reason().as_deref(), Some("test"));
+        assert!(!t.trigger("again"));
+    }
+
+    // The child side of `signals`, runs only when started by it.
+    #[test]
+    fn child() {
+        let mode = match env::var(CHILD) {
+            Ok(mode) => mode,
+            Err(_)   => return,
+        };
+
+        set_grace(Duration::from_millis(800));
+        install().unwrap();
+        on_reload(|| { println!("reloaded"); Ok(()) });
+        hook("first", Duration::from_secs(1), || println!("hook first"));
+        if mode == "stuck" {
+            hook("stuck", Duration::from_millis(100), || thread::sleep(Duration::from_secs(60)));
+        }
+        hook("second", Duration::from_secs(1), || println!("hook second"));
+        println!("ready");
+        if mode == "ignore" {
+            // `main` never looks at the token
+            thread::sleep(Duration::from_secs(60));
+        }
+
+        let token = super::token();
+        token.wait();
+        println!("token {}", token.reason().unwrap_or_default());
+        if mode == "stuck" {
+            // never returns, the grace period ends the process
+            thread::sleep(Duration::from_secs(60));
+        }
+        finish();
+        println!("finished");
+        process::exit(exit::OK);
+    }
+
+    fn spawn(mode: &str) -> (process::Child, impl Iterator<Item = String>) {
+        let mut child = Command::new(env::current_exe().unwrap())
+            .args(["shutdown::tests::child", "--exact", "--nocapture", "--test-threads=1"])
+            .env(CHILD, mode)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let lines = BufReader::new(child.stdout.take().unwrap()).lines().map_while(Result::ok);
+        (child, lines)
+    }
+
+    fn kill(child: &process::Child, sig: c_int) {
+        assert_eq!(unsafe { libc::kill(child.id() as libc::pid_t, sig) }, 0);
+    }
+
+    // libtest prints the first line after the test name
+    fn expect(lines: &mut impl Iterator<Item = String>, want: &str) {
+        assert!(lines.any(|l| l.ends_with(want)), "no line {:?}", want);
+    }
+
+    #[test]
+    fn signals() {
+        let (mut child, mut lines) = spawn("ok");
+        expect(&mut lines, "ready");
+
+        kill(&child, libc::SIGHUP);
+        expect(&mut lines, "reloaded");
+
+        kill(&child, libc::SIGTERM);
+        let rest: Vec<String> = lines.collect();
+        let pos = |l: &str| rest.iter().position(|r| r == l).unwrap_or_else(|| panic!("{}", l));
+        assert!(pos("token SIGTERM") < pos("finished"));
+        assert!(pos("hook first") < pos("hook second"));
+        assert!(pos("hook second") < pos("finished"));
+        assert_eq!(child.wait().unwrap().code(), Some(exit::OK));
+    }
+
+    #[test]
+    fn grace_period() {
+        let (mut child, mut lines) = spawn("stuck");
+        expect(&mut lines, "ready");
+
+        let start = Instant::now();
+        kill(&child, libc::SIGINT);
+        let rest: Vec<String> = lines.collect();
+        assert!(rest.contains(&"hook second".to_string()), "{:?}", rest);
+        assert_eq!(child.wait().unwrap().code(), Some(exit::FAILURE));
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn no_token() {
+        let (mut child, mut lines) = spawn("ignore");
+        expect(&mut lines, "ready");
+
+        let start = Instant::now();
+        kill(&child, libc::SIGTERM);
+        let rest: Vec<String> = lines.collect();
+        assert!(rest.contains(&"hook second".to_string()), "{:?}", rest);
+        assert_eq!(child.wait().unwrap().code(), Some(128 + libc::SIGTERM));
+        assert!(start.elapsed() < Duration::from_millis(800));
+    }
+}
@@ -0,0 +1,319 @@
+use std::env;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::net::TcpListener;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{UnixDatagram, UnixListener};
+use std::path::Path;
+use std::process;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+use super::error::{YiError, YiErrorKind, YiResult, YiResultExt};
+use super::health::Health;
+use super::shutdown;
+
+// the first descriptor passed by socket activation
+const LISTEN_FDS_START: RawFd = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    Notify,
+    Socket,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Notify => write!(f, "notifying systemd"),
+            Error::Socket => write!(f, "socket from systemd"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Notify => "YI-SYS-001",
+            Error::Socket => "YI-SYS-002",
+        }
+    }
+}
+
+impl From<Error> for YiErrorKind {
+    fn from(e: Error) -> YiErrorKind {
+        YiErrorKind::Code(e.code(), e.to_string())
+    }
+}
+
+impl From<Error> for YiError {
+    fn from(e: Error) -> YiError {
+        YiError::from(YiErrorKind::from(e))
+    }
+}
+
+// started by systemd with `Type=notify`
+pub fn is_active() -> bool {
+    env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+// Send `state` lines, e.g. `[("READY", "1")]`, to `NOTIFY_SOCKET`, false
+// when the process isn't started by systemd.
+pub fn notify(state: &[(&str, &str)]) -> YiResult<bool> {
+    match env::var("NOTIFY_SOCKET") {
+        Ok(path) if !path.is_empty() => notify_to(&path, state).map(|_| true),
+        _ => Ok(false),
+    }
+}
+
+// `notify` to the socket at `path`, `@` starts an abstract name
+pub fn notify_to(path: &str, state: &[(&str, &str)]) -> YiResult<()> {
+    let text: String = state.iter().map(|(k, v)| format!("{}={}\n", k, v)).collect();
+
+    let socket = UnixDatagram::unbound().to_yikind(Error::Notify)?;
+    send(&socket, path, text.as_bytes()).to_yikind(Error::Notify).with_field("socket", path)?;
+    Ok(())
+}
+
+fn send(socket: &UnixDatagram, path: &str, text: &[u8]) -> io::Result<usize> {
+    match path.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(text, &addr)
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => Err(io::Error::new(io::ErrorKind::Unsupported, "abstract socket")),
+        None => socket.send_to(text, Path::new(path)),
+    }
+}
+
+pub fn ready() -> YiResult<bool> {
+    notify(&[("READY", "1")])
+}
+
+pub fn status(status: &str) -> YiResult<bool> {
+    notify(&[("STATUS", status)])
+}
+
+pub fn stopping() -> YiResult<bool> {
+    notify(&[("STOPPING", "1")])
+}
+
+pub fn watchdog() -> YiResult<bool> {
+    notify(&[("WATCHDOG", "1")])
+}
+
+// `WatchdogSec=` of the unit when it is meant for this process
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+    env::var("WATCHDOG_USEC").ok().and_then(|us| us.parse().ok())
+        .filter(|us| *us > 0).map(Duration::from_micros)
+}
+
+// Send `WATCHDOG=1` at half the interval while `health` isn't down, until
+// shutdown. Returns false without a watchdog.
+pub fn spawn_watchdog(health: Arc<Health>) -> bool {
+    let interval = match watchdog_interval() {
+        Some(i) => i / 2,
+        None    => return false,
+    };
+
+    let token = shutdown::internal_token();
+    thread::Builder::new().name("watchdog".to_string()).spawn(move || {
+        while !token.wait_timeout(interval) {
+            // a missed ping lets systemd restart the process
+            if !health.snapshot().healthy() {
+                log::warn!("unhealthy, no watchdog ping");
+                continue;
+            }
+            if let Err(e) = watchdog() {
+                log::warn!("{:#}", e);
+            }
+        }
+    }).is_ok()
+}
+
+#[derive(Debug)]
+pub enum Socket {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+// Descriptors of socket activation by `FileDescriptorName=`, "unknown"
+// without names, empty when they are meant for another process.
+fn parse(pid: Option<&str>, fds: Option<&str>, names: Option<&str>) -> Vec<(String, RawFd)> {
+    if pid.and_then(|p| p.parse::<u32>().ok()) != Some(process::id()) {
+        return Vec::new();
+    }
+    let n = fds.and_then(|n| n.parse::<RawFd>().ok()).unwrap_or(0);
+    let names: Vec<&str> = names.map(|n| n.split(':').collect()).unwrap_or_default();
+    (0..n).map(|i| {
+        let name = names.get(i as usize).copied().unwrap_or("unknown");
+        (name.to_string(), LISTEN_FDS_START + i)
+    }).collect()
+}
+
+fn listen_fds() -> &'static Mutex<Vec<(String, RawFd)>> {
+    static FDS: OnceLock<Mutex<Vec<(String, RawFd)>>> = OnceLock::new();
+    FDS.get_or_init(|| {
+        let fds = parse(env::var("LISTEN_PID").ok().as_deref(), env::var("LISTEN_FDS").ok().as_deref(),
+                        env::var("LISTEN_FDNAMES").ok().as_deref());
+        for (_, fd) in &fds {
+            // not passed on to children
+            unsafe { libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+        Mutex::new(fds)
+    })
+}
+
+// names of the sockets not taken yet
+pub fn socket_names() -> Vec<String> {
+    listen_fds().lock().unwrap_or_else(|e| e.into_inner()).iter().map(|(n, _)| n.clone()).collect()
+}
+
+// Take the listening socket named `name`, a socket is taken once.
+pub fn take(name: &str) -> YiResult<Socket> {
+    let mut fds = listen_fds().lock().unwrap_or_else(|e| e.into_inner());
+    let i = fds.iter().position(|(n, _)| n == name).ok_or_else(|| {
+        YiError::from(Error::Socket).with_field("name", name)
+            .hint("set FileDescriptorName= in the .socket unit, LISTEN_FDS passes none by that name")
+    })?;
+    let (_, fd) = fds.remove(i);
+
+    let mut kind: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    if unsafe { libc::getsockopt(fd, libc::SOL_SOCKET, libc::SO_TYPE,
+                                 &mut kind as *mut _ as *mut libc::c_void, &mut len) } != 0 {
+        return Err(io::Error::last_os_error()).to_yikind(Error::Socket).with_field("name", name);
+    }
+    if kind != libc::SOCK_STREAM {
+        return Err(YiError::from(Error::Socket).with_field("name", name)
+                   .hint("only stream sockets are supported, check ListenStream= in the .socket unit"));
+    }
+
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    if unsafe { libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) } != 0 {
+        return Err(io::Error::last_os_error()).to_yikind(Error::Socket).with_field("name", name);
+    }
+    Ok(match addr.ss_family as libc::c_int {
+        libc::AF_UNIX => Socket::Unix(unsafe { UnixListener::from_raw_fd(fd) }),
+        _             => Socket::Tcp(unsafe { TcpListener::from_raw_fd(fd) }),
+    })
+}
+
+// A service unit for `Type=notify`, printed by the `unit` subcommand.
+#[derive(Debug, Clone, Default)]
+pub struct Unit {
+    pub description: String,
+    pub exec_start: Vec<String>,
+    pub working_directory: String,
+    pub watchdog_sec: Option<u64>,
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let exec: Vec<String> = self.exec_start.iter().map(|a| quote(a)).collect();
+
+        writeln!(f, "[Unit]")?;
+        writeln!(f, "Description={}", self.description)?;
+        writeln!(f, "Wants=network-online.target")?;
+        writeln!(f, "After=network-online.target")?;
+        writeln!(f)?;
+        writeln!(f, "[Service]")?;
+        writeln!(f, "Type=notify")?;
+        writeln!(f, "ExecStart={}", exec.join(" "))?;
+        writeln!(f, "WorkingDirectory={}", self.working_directory.replace('%', "%%"))?;
+        writeln!(f, "Restart=on-failure")?;
+        if let Some(sec) = self.watchdog_sec {
+            writeln!(f, "WatchdogSec={}", sec)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "[Install]")?;
+        writeln!(f, "WantedBy=multi-user.target")
+    }
+}
+
+// systemd's quoting of command lines, `%` and `$` would be specifiers
+fn quote(arg: &str) -> String {
+    let arg = arg.replace('%', "%%").replace('$', "$$");
+    if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\') {
+        format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        arg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+
+    #[test]
+    fn notify_socket() {
+        let path = env::temp_dir().join(format!("yiapp-notify-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let name = path.to_str().unwrap();
+        notify_to(name, &[("READY", "1")]).unwrap();
+        notify_to(name, &[("STATUS", "serving"), ("WATCHDOG", "1")]).unwrap();
+
+        let mut buf = [0u8; 256];
+        let n = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1\n");
+        let n = socket.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"STATUS=serving\nWATCHDOG=1\n");
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(notify_to(name, &[("STATUS", "gone")]).unwrap_err().code(), "YI-SYS-001");
+    }
+
+    #[test]
+    fn listen_fds() {
+        let pid = process::id().to_string();
+        assert_eq!(parse(Some(&pid), Some("2"), Some("web:admin")),
+                   [("web".to_string(), 3), ("admin".to_string(), 4)]);
+        assert_eq!(parse(Some(&pid), Some("1"), None), [("unknown".to_string(), 3)]);
+        assert!(parse(Some("1"), Some("1"), None).is_empty());
+        assert!(parse(None, None, None).is_empty());
+        assert_eq!(take("web").unwrap_err().code(), "YI-SYS-002");
+
+        // a datagram socket is not a listener
+        let udp = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        super::listen_fds().lock().unwrap().push(("udp".to_string(), udp.as_raw_fd()));
+        assert_eq!(take("udp").unwrap_err().code(), "YI-SYS-002");
+        let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+        super::listen_fds().lock().unwrap().push(("tcp".to_string(), tcp.into_raw_fd()));
+        assert!(matches!(take("tcp").unwrap(), Socket::Tcp(_)));
+    }
+
+    #[test]
+    fn unit() {
+        let unit = Unit {
+            description: "My app".to_string(),
+            exec_start: vec!["/opt/my app/bin".to_string(), "--rate=5%".to_string()],
+            working_directory: "/opt/my app".to_string(),
+            watchdog_sec: Some(30),
+        }.to_string();
+
+        assert!(unit.contains("Description=My app\n"));
+        assert!(unit.contains("Type=notify\n"));
+        assert!(unit.contains("ExecStart=\"/opt/my app/bin\" --rate=5%%\n"));
+        assert!(unit.contains("WorkingDirectory=/opt/my app\n"));
+        assert!(unit.contains("WatchdogSec=30\n"));
+        assert_eq!(quote(r#"a"b"#), r#""a\"b""#);
+    }
+}
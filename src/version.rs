@@ -1,7 +1,13 @@
 use std::fmt;
+use std::cmp::Ordering;
+use std::str::FromStr;
+use serde::Serialize;
+
+use super::error::{YiError, YiErrorKind, YiResult};
 
 type VerStr = &'static str;
 
+#[derive(Debug, Clone, Serialize)]
 pub struct Version {
     pub name: VerStr,
     pub major: VerStr,
@@ -14,30 +20,58 @@ pub struct Version {
     pub short_hash: VerStr,
     pub hash: VerStr,
     pub date: VerStr,
+    pub dirty: bool,
+    pub branch: VerStr,
+    pub tag: VerStr,
+    // where the commit fields came from: env, git, cargo_vcs_info, file or empty
+    pub source: VerStr,
+
+    pub rustc: VerStr,
+    pub target: VerStr,
+    pub profile: VerStr,
+    pub features: VerStr,
+    pub build_time: VerStr,
 }
 
 impl Version {
+    // yiapp's own version, applications use `yiapp_version!()`
     pub fn new() -> Self {
-        Version {
-            name: env!("CARGO_PKG_NAME"),
-            major: env!("CARGO_PKG_VERSION_MAJOR"),
-            minor: env!("CARGO_PKG_VERSION_MINOR"),
-            patch: env!("CARGO_PKG_VERSION_PATCH"),
-            release: env!("SRC_RELEASE"),
-            pre_release: env!("CARGO_PKG_VERSION_PRE"),
-
-            channel: env!("SRC_CHANNEL"),
-            short_hash: env!("SRC_SHORT_HASH"),
-            hash: env!("SRC_HASH"),
-            date: env!("SRC_DATE"),
-        }
+        crate::yiapp_version!()
     }
 
     pub fn full() -> String {
-        let version = Version::new();
+        Version::new().to_full()
+    }
+
+    // `--version` output, clap prefixes it with the app name
+    pub fn short(&self) -> String {
+        let if_empty = |str: &str, r| {
+            if str.is_empty() { "".to_string() }  else { r }
+        };
 
-        let mut version_string = String::from(version.to_string());
-        version_string.push_str("\n");
+        format!("{}{}{}", self.release,
+                if_empty(self.channel, format!("-{}", self.channel)),
+                if_empty(self.short_hash,
+                         format!(" ({}{} {})", self.short_hash,
+                                 if self.dirty { "-dirty" } else { "" }, self.date)))
+    }
+
+    pub fn semver(&self) -> YiResult<SemVer> {
+        let pre = if self.pre_release.is_empty() { String::new() }
+                  else { format!("-{}", self.pre_release) };
+        format!("{}.{}.{}{}", self.major, self.minor, self.patch, pre).parse()
+    }
+
+    // e.g. `version.require(">=1.2, <2")?` before reading state written by a peer
+    pub fn require(&self, req: &str) -> YiResult<()> {
+        req.parse::<VersionReq>()?.check(&self.semver()?)
+    }
+
+    pub fn to_full(&self) -> String {
+        let version = self;
+
+        let mut version_string = version.to_string();
+        version_string.push('\n');
 
         let or_push = |field: &str, label: &str, out: &mut String| {
             if !field.is_empty() {
@@ -45,35 +79,430 @@ impl Version {
             }
         };
 
-        or_push(version.release, "....release", &mut version_string);
-        or_push(version.channel, "....channel", &mut version_string);
-        or_push(version.hash,    "commit-hash", &mut version_string);
-        or_push(version.date,    "commit-date", &mut version_string);
+        let dirty = if version.dirty { "yes" } else { "" };
+
+        or_push(version.release,    "....release", &mut version_string);
+        or_push(version.channel,    "....channel", &mut version_string);
+        or_push(version.hash,       "commit-hash", &mut version_string);
+        or_push(version.date,       "commit-date", &mut version_string);
+        or_push(dirty,              "......dirty", &mut version_string);
+        or_push(version.branch,     ".....branch", &mut version_string);
+        or_push(version.tag,        "........tag", &mut version_string);
+        or_push(version.source,     ".....source", &mut version_string);
+        or_push(version.rustc,      "......rustc", &mut version_string);
+        or_push(version.target,     ".....target", &mut version_string);
+        or_push(version.profile,    "....profile", &mut version_string);
+        or_push(version.features,   "...features", &mut version_string);
+        or_push(version.build_time, ".build-time", &mut version_string);
 
         version_string
     }
 }
 
+impl Default for Version {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl fmt::Display for Version {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let if_empty = |str: &str, r| {
-            if str.is_empty() { "".to_string() }  else { r }
-        };
+        write!(f, "{} {}", self.name, self.short())
+    }
+}
+
+// Semantic version, https://semver.org
+#[derive(Debug, Clone, Eq)]
+pub struct SemVer {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre: Vec<Ident>,
+    // ignored for ordering and equality
+    pub build: String,
+}
 
-        write!(f, "{} {}{}{}", self.name, self.release,
-               if_empty(self.channel, format!("-{}", self.channel)),
-               if_empty(self.short_hash,
-                        format!(" ({} {})", self.short_hash, self.date)))?;
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Ident {
+    // numeric identifiers always have lower precedence than alphanumeric ones
+    Numeric(u64),
+    Alpha(String),
+}
+
+impl SemVer {
+    pub fn new(major: u64, minor: u64, patch: u64) -> Self {
+        SemVer { major, minor, patch, pre: Vec::new(), build: String::new() }
+    }
+
+    pub fn is_pre(&self) -> bool {
+        !self.pre.is_empty()
+    }
+}
+
+fn invalid(s: &str) -> YiError {
+    YiError::from(format!("invalid version: {}", s))
+}
+
+fn number(s: &str, whole: &str) -> YiResult<u64> {
+    if s.is_empty() || (s.len() > 1 && s.starts_with('0')) {
+        return Err(invalid(whole));
+    }
+    s.parse().map_err(|_| invalid(whole))
+}
+
+fn idents(s: &str, whole: &str) -> YiResult<Vec<Ident>> {
+    s.split('.').map(|id| {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            Err(invalid(whole))
+        } else if id.chars().all(|c| c.is_ascii_digit()) {
+            number(id, whole).map(Ident::Numeric)
+        } else {
+            Ok(Ident::Alpha(id.to_string()))
+        }
+    }).collect()
+}
+
+impl FromStr for SemVer {
+    type Err = YiError;
+
+    fn from_str(s: &str) -> YiResult<Self> {
+        let text = s.trim().trim_start_matches('v');
+        let (text, build) = text.split_once('+').map_or((text, None), |(t, b)| (t, Some(b)));
+        let (text, pre) = text.split_once('-').map_or((text, None), |(t, p)| (t, Some(p)));
+
+        let mut nums = text.split('.');
+        let mut next = || number(nums.next().unwrap_or(""), s);
+        let mut ver = SemVer::new(next()?, next()?, next()?);
+        if nums.next().is_some() {
+            return Err(invalid(s));
+        }
+
+        if let Some(pre) = pre {
+            ver.pre = idents(pre, s)?;
+        }
+        if let Some(build) = build {
+            idents(build, s)?;
+            ver.build = build.to_string();
+        }
+
+        Ok(ver)
+    }
+}
+
+impl Ord for SemVer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch).cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.is_pre(), other.is_pre()) {
+                // 1.0.0-alpha < 1.0.0
+                (true, false) => Ordering::Less,
+                (false, true) => Ordering::Greater,
+                _             => self.pre.cmp(&other.pre),
+            })
+    }
+}
+
+impl PartialOrd for SemVer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SemVer {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl fmt::Display for Ident {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ident::Numeric(n) => write!(f, "{}", n),
+            Ident::Alpha(s)   => write!(f, "{}", s),
+        }
+    }
+}
+
+impl fmt::Display for SemVer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        if self.is_pre() {
+            let pre: Vec<String> = self.pre.iter().map(|i| i.to_string()).collect();
+            write!(f, "-{}", pre.join("."))?;
+        }
+        if !self.build.is_empty() {
+            write!(f, "+{}", self.build)?;
+        }
         Ok(())
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Exact,
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Tilde,
+    Caret,
+    Wildcard,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Comparator {
+    op: Op,
+    major: u64,
+    minor: Option<u64>,
+    patch: Option<u64>,
+    pre: Vec<Ident>,
+}
+
+// Version requirement with cargo's syntax, e.g. `>=1.2, <2`, `^0.3`, `~1.2.3`, `1.*`.
+// A bare version means `^`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VersionReq {
+    text: String,
+    comparators: Vec<Comparator>,
+}
+
+impl FromStr for Comparator {
+    type Err = YiError;
+
+    fn from_str(s: &str) -> YiResult<Self> {
+        let s = s.trim();
+        let (op, rest) = [(">=", Op::GreaterEq), ("<=", Op::LessEq), (">", Op::Greater),
+                          ("<", Op::Less), ("=", Op::Exact), ("~", Op::Tilde), ("^", Op::Caret)]
+            .iter()
+            .find(|(p, _)| s.starts_with(p))
+            .map_or((Op::Caret, s), |(p, op)| (*op, s[p.len()..].trim()));
+
+        let (text, pre) = rest.split_once('-').unwrap_or((rest, ""));
+        let mut parts = text.split('.');
+        let wild = |p: &str| p == "*" || p == "x" || p == "X";
+
+        let mut cmp = Comparator { op, major: 0, minor: None, patch: None, pre: Vec::new() };
+        match parts.next() {
+            // `*` matches any release
+            Some(p) if wild(p) && op == Op::Caret => {
+                cmp.op = Op::GreaterEq;
+                return Ok(cmp);
+            }
+            Some(p) => cmp.major = number(p, s)?,
+            None    => return Err(invalid(s)),
+        }
+        for slot in [&mut cmp.minor, &mut cmp.patch] {
+            match parts.next() {
+                Some(p) if wild(p) => { cmp.op = Op::Wildcard; break; }
+                Some(p) => *slot = Some(number(p, s)?),
+                None    => break,
+            }
+        }
+        if parts.next().is_some() {
+            return Err(invalid(s));
+        }
+        if !pre.is_empty() {
+            if cmp.patch.is_none() {
+                return Err(invalid(s));
+            }
+            cmp.pre = idents(pre, s)?;
+        }
+
+        Ok(cmp)
+    }
+}
+
+impl Comparator {
+    fn matches(&self, v: &SemVer) -> bool {
+        let base = SemVer {
+            major: self.major,
+            minor: self.minor.unwrap_or(0),
+            patch: self.patch.unwrap_or(0),
+            pre: self.pre.clone(),
+            build: String::new(),
+        };
+        let (major, minor) = (v.major == self.major, Some(v.minor) == self.minor);
+
+        match (self.op, self.minor, self.patch) {
+            (Op::Wildcard, None, _)         => major,
+            (Op::Wildcard, Some(_), _)      => major && minor,
+            (Op::Exact, _, Some(_))         => *v == base,
+            (Op::Exact, Some(_), None)      => major && minor,
+            (Op::Exact, None, _)            => major,
+            (Op::Greater, _, Some(_))       => *v > base,
+            (Op::Greater, Some(m), None)    => (v.major, v.minor) > (self.major, m),
+            (Op::Greater, None, _)          => v.major > self.major,
+            (Op::GreaterEq, _, _)           => *v >= base,
+            (Op::Less, _, _)                => *v < base,
+            (Op::LessEq, _, Some(_))        => *v <= base,
+            (Op::LessEq, Some(m), None)     => (v.major, v.minor) <= (self.major, m),
+            (Op::LessEq, None, _)           => v.major <= self.major,
+            (Op::Tilde, Some(_), _)         => major && minor && *v >= base,
+            (Op::Tilde, None, _)            => major,
+            (Op::Caret, None, _)            => major,
+            (Op::Caret, Some(m), None)      => major && (if self.major > 0 { v.minor >= m }
+                                                         else { v.minor == m }),
+            (Op::Caret, Some(m), Some(p))   => major && *v >= base && match (self.major, m) {
+                (0, 0) => v.minor == 0 && v.patch == p,
+                (0, _) => v.minor == m,
+                _      => true,
+            },
+        }
+    }
+}
+
+impl FromStr for VersionReq {
+    type Err = YiError;
+
+    fn from_str(s: &str) -> YiResult<Self> {
+        let comparators = s.split(',')
+            .map(|c| c.parse())
+            .collect::<YiResult<Vec<Comparator>>>()?;
+
+        Ok(VersionReq { text: s.trim().to_string(), comparators })
+    }
+}
+
+impl VersionReq {
+    pub fn matches(&self, v: &SemVer) -> bool {
+        // pre-releases only match a comparator naming the same major.minor.patch
+        if v.is_pre() && !self.comparators.iter().any(|c| {
+            !c.pre.is_empty() && (c.major, c.minor, c.patch) == (v.major, Some(v.minor), Some(v.patch))
+        }) {
+            return false;
+        }
+
+        self.comparators.iter().all(|c| c.matches(v))
+    }
+
+    pub fn check(&self, v: &SemVer) -> YiResult<()> {
+        if self.matches(v) {
+            Ok(())
+        } else {
+            Err(YiErrorKind::Ver { found: v.to_string(), required: self.text.clone() }.into())
+        }
+    }
+}
+
+impl fmt::Display for VersionReq {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+mod macros {
+    // Version of the calling crate, its build.rs must call `yiapp::build::emit()`.
+    #[macro_export] macro_rules! yiapp_version {
+        () => {
+            $crate::version::Version {
+                name: env!("CARGO_PKG_NAME"),
+                major: env!("CARGO_PKG_VERSION_MAJOR"),
+                minor: env!("CARGO_PKG_VERSION_MINOR"),
+                patch: env!("CARGO_PKG_VERSION_PATCH"),
+                release: env!("SRC_RELEASE"),
+                pre_release: env!("CARGO_PKG_VERSION_PRE"),
+
+                channel: env!("SRC_CHANNEL"),
+                short_hash: env!("SRC_SHORT_HASH"),
+                hash: env!("SRC_HASH"),
+                date: env!("SRC_DATE"),
+                dirty: env!("SRC_DIRTY") == "true",
+                branch: env!("SRC_BRANCH"),
+                tag: env!("SRC_TAG"),
+                source: env!("SRC_SOURCE"),
+
+                rustc: env!("SRC_RUSTC"),
+                target: env!("SRC_TARGET"),
+                profile: env!("SRC_PROFILE"),
+                features: env!("SRC_FEATURES"),
+                build_time: env!("SRC_BUILD_TIME"),
+            }
+        };
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    // #[test]
+    #[test]
+    fn json() {
+        let v: serde_json::Value = serde_json::to_value(Version::new()).unwrap();
+        assert_eq!(v["name"], env!("CARGO_PKG_NAME"));
+        assert_eq!(v["hash"], env!("SRC_HASH"));
+    }
+
+    fn v(s: &str) -> SemVer {
+        s.parse().unwrap()
+    }
+
+    fn req(r: &str, s: &str) -> bool {
+        r.parse::<VersionReq>().unwrap().matches(&v(s))
+    }
+
+    #[test]
+    fn semver_parse() {
+        assert_eq!(v("1.2.3"), SemVer::new(1, 2, 3));
+        assert_eq!(v("1.2.3-rc.1+build.5").to_string(), "1.2.3-rc.1+build.5");
+        assert_eq!(v("v0.1.0"), SemVer::new(0, 1, 0));
+        for bad in &["1.2", "1.2.3.4", "01.2.3", "1.2.x", "1.2.3-", "1.2.3-a..b", ""] {
+            assert!(bad.parse::<SemVer>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn semver_order() {
+        let order = ["1.0.0-alpha", "1.0.0-alpha.1", "1.0.0-alpha.beta", "1.0.0-beta",
+                     "1.0.0-beta.2", "1.0.0-beta.11", "1.0.0-rc.1", "1.0.0", "1.0.1", "1.1.0",
+                     "2.0.0"];
+        for w in order.windows(2) {
+            assert!(v(w[0]) < v(w[1]), "{} < {}", w[0], w[1]);
+        }
+        assert_eq!(v("1.0.0+a"), v("1.0.0+b"));
+    }
+
+    #[test]
+    fn requirement() {
+        assert!(req(">=1.2, <2", "1.2.0"));
+        assert!(req(">=1.2, <2", "1.9.9"));
+        assert!(!req(">=1.2, <2", "2.0.0"));
+        assert!(!req(">=1.2, <2", "1.1.9"));
+        assert!(req("1.2", "1.5.0"));
+        assert!(!req("1.2", "2.0.0"));
+        assert!(req("^0.3", "0.3.9"));
+        assert!(!req("^0.3", "0.4.0"));
+        assert!(req("^0.0.3", "0.0.3"));
+        assert!(!req("^0.0.3", "0.0.4"));
+        assert!(req("~1.2.3", "1.2.9"));
+        assert!(!req("~1.2.3", "1.3.0"));
+        assert!(req("=1.2.3", "1.2.3"));
+        assert!(req("1.*", "1.7.0"));
+        assert!(!req("1.*", "2.0.0"));
+        assert!(req("*", "3.1.4"));
+        assert!(req("<=1.2", "1.2.9"));
+        assert!(!req(">1.2", "1.2.9"));
+        assert!(!req(">=1.0", "1.1.0-rc.1"));
+        assert!(req(">=1.1.0-rc.1", "1.1.0-rc.2"));
+        assert!("1.2.3.4".parse::<VersionReq>().is_err());
+    }
+
+    #[test]
+    fn mismatch() {
+        let e = "<1".parse::<VersionReq>().unwrap().check(&v("1.0.0")).unwrap_err();
+        match e.kind() {
+            YiErrorKind::Ver { found, required } => {
+                assert_eq!(found, "1.0.0");
+                assert_eq!(required, "<1");
+            }
+            k => panic!("unexpected {:?}", k),
+        }
+        assert!(Version::new().require(">=0.1").is_ok());
+    }
+
+    #[test]
     fn ver() {
-        assert!(false, format!("\n{}", Version::full()));
+        let full = Version::full();
+        assert!(full.starts_with(&Version::new().to_string()), "\n{}", full);
+        assert!(full.contains("....release"), "\n{}", full);
+        assert!(full.contains(".....target"), "\n{}", full);
     }
 }